@@ -3,21 +3,72 @@ create table feeds (
     title text not null,
     feed_uri text unique not null,
     site_uri text,
-    updated_at timestamp with time zone not null
+    updated_at timestamp with time zone not null,
+    last_fetched_at timestamp with time zone,
+    next_fetch_at timestamp with time zone not null default now(),
+    -- seconds between fetches, null for the default interval
+    fetch_interval bigint,
+    -- validators of the last response, sent back to make fetches conditional
+    etag text,
+    last_modified text,
+    last_success_at timestamp with time zone,
+    last_error text,
+    last_error_kind text,
+    consecutive_failures integer not null default 0,
+    -- download and extract the article behind each new item
+    full_text bool not null default false
 );
 
 create table items (
     id bigserial primary key,
     feed_id bigint not null references feeds(id) on delete cascade,
-    hash text unique not null,
+    -- id of the entry in its feed, if the feed gives one
+    guid text,
+    hash text not null,
     link text,
     title text not null,
     author text not null,
     content text not null,
+    -- article extracted from the item link, for feeds with full_text
+    extracted_content text,
+    summary text,
+    content_type text,
+    categories text[] not null default '{}',
+    thumbnail text,
     created_at timestamp with time zone not null,
     updated_at timestamp with time zone not null,
+    crawled_at timestamp with time zone not null default now(),
     read bool not null default false,
-    star bool not null default false
+    star bool not null default false,
+    -- seconds into the enclosure where playback was left
+    playback_position double precision,
+    unique (feed_id, guid)
+);
+
+create index items_feed_id_hash_idx on items (feed_id, hash);
+-- Streams are listed and paged in this order.
+create index items_crawled_at_id_idx on items (crawled_at, id);
+
+create table enclosures (
+    id bigserial primary key,
+    item_id bigint not null references items(id) on delete cascade,
+    url text not null,
+    mime_type text,
+    length bigint,
+    -- seconds
+    duration bigint,
+    image text,
+    unique (item_id, url)
+);
+
+create table icons (
+    feed_id bigint primary key references feeds(id) on delete cascade,
+    -- both null when no icon was found
+    mime_type text,
+    data bytea,
+    -- where the icon was found, tried first on refreshes
+    source_uri text,
+    fetched_at timestamptz not null default now()
 );
 
 create table tags (
@@ -33,7 +84,36 @@ create table taggings (
     unique (feed_id, tag_id)
 );
 
+create table item_taggings (
+    item_id bigint references items(id) on delete cascade,
+    tag_id bigint references tags(id) on delete cascade,
+    unique (item_id, tag_id)
+);
+
 create table session (
-    password text not null,
-    token text not null
-);
\ No newline at end of file
+    id bigserial primary key,
+    email text unique not null,
+    password text not null
+);
+
+create table auth_tokens (
+    token text primary key,
+    session_id bigint not null references session(id) on delete cascade,
+    created_at timestamp with time zone not null,
+    expires_at timestamp with time zone not null,
+    revoked bool not null default false
+);
+
+create table edit_tokens (
+    token text primary key,
+    auth_token text not null references auth_tokens(token) on delete cascade,
+    expires_at timestamp with time zone not null
+);
+
+create table stream_prefs (
+    session_id bigint not null references session(id) on delete cascade,
+    stream_id text not null,
+    key text not null,
+    value text not null,
+    primary key (session_id, stream_id, key)
+);
@@ -0,0 +1,104 @@
+-- Bring a database created by an earlier migrate.sql up to date. Safe to run
+-- more than once: psql -d liu-feed -f migrations/upgrade.sql
+
+begin;
+
+alter table feeds
+    add column if not exists last_fetched_at timestamp with time zone,
+    add column if not exists next_fetch_at timestamp with time zone not null default now(),
+    add column if not exists fetch_interval bigint,
+    add column if not exists etag text,
+    add column if not exists last_modified text,
+    add column if not exists last_success_at timestamp with time zone,
+    add column if not exists last_error text,
+    add column if not exists last_error_kind text,
+    add column if not exists consecutive_failures integer not null default 0,
+    add column if not exists full_text bool not null default false;
+
+-- The same entry may appear in several feeds, so hashes are only unique within a feed.
+alter table items drop constraint if exists items_hash_key;
+alter table items
+    add column if not exists guid text,
+    add column if not exists extracted_content text,
+    add column if not exists summary text,
+    add column if not exists content_type text,
+    add column if not exists categories text[] not null default '{}',
+    add column if not exists thumbnail text,
+    add column if not exists crawled_at timestamp with time zone,
+    add column if not exists playback_position double precision;
+-- Items stored before count as crawled when they were published.
+update items set crawled_at = created_at where crawled_at is null;
+alter table items
+    alter column crawled_at set default now(),
+    alter column crawled_at set not null;
+create unique index if not exists items_feed_id_guid_key on items (feed_id, guid);
+create index if not exists items_feed_id_hash_idx on items (feed_id, hash);
+create index if not exists items_crawled_at_id_idx on items (crawled_at, id);
+
+create table if not exists enclosures (
+    id bigserial primary key,
+    item_id bigint not null references items(id) on delete cascade,
+    url text not null,
+    mime_type text,
+    length bigint,
+    -- seconds
+    duration bigint,
+    image text,
+    unique (item_id, url)
+);
+
+create table if not exists icons (
+    feed_id bigint primary key references feeds(id) on delete cascade,
+    -- both null when no icon was found
+    mime_type text,
+    data bytea,
+    fetched_at timestamptz not null default now()
+);
+alter table icons add column if not exists source_uri text;
+
+create table if not exists item_taggings (
+    item_id bigint references items(id) on delete cascade,
+    tag_id bigint references tags(id) on delete cascade,
+    unique (item_id, tag_id)
+);
+
+-- The old session table kept a plain password and a single token; set the
+-- account again with `liu-feed passwd <email>` afterwards.
+do $$
+begin
+    if not exists (select 1 from information_schema.columns
+                   where table_name = 'session' and column_name = 'email') then
+        drop table if exists session;
+    end if;
+end
+$$;
+
+create table if not exists session (
+    id bigserial primary key,
+    email text unique not null,
+    password text not null
+);
+
+create table if not exists auth_tokens (
+    token text primary key,
+    session_id bigint not null references session(id) on delete cascade,
+    created_at timestamp with time zone not null,
+    expires_at timestamp with time zone not null,
+    revoked bool not null default false
+);
+
+create table if not exists edit_tokens (
+    token text primary key,
+    auth_token text not null references auth_tokens(token) on delete cascade,
+    expires_at timestamp with time zone not null
+);
+
+create table if not exists stream_prefs (
+    session_id bigint not null references session(id) on delete cascade,
+    stream_id text not null,
+    key text not null,
+    value text not null,
+    primary key (session_id, stream_id, key)
+);
+
+commit;
@@ -1,7 +1,10 @@
 use sqlx::PgPool;
 use handlebars::Handlebars;
+use crate::proxy::Proxy;
 
 pub struct State<'a> {
     pub pool: PgPool,
     pub hb: Handlebars<'a>,
-}
\ No newline at end of file
+    pub proxy: Proxy,
+    pub client: reqwest::Client,
+}
@@ -0,0 +1,171 @@
+use crate::error::Result;
+use crate::model::feed::Download;
+use crate::http::{check_status, read_body, MAX_BODY_SIZE};
+use futures::{stream, StreamExt};
+use reqwest::Client;
+use scraper::{Html, Selector};
+use url::Url;
+
+/// Paths probed when a page does not advertise its feeds.
+const COMMON_FEED_PATHS: [&str; 5] = ["/feed", "/rss.xml", "/atom.xml", "/feed.xml", "/index.xml"];
+
+/// Common paths requested at once while probing.
+const PROBE_CONCURRENCY: usize = 3;
+
+const FEED_TYPES: [&str; 3] = [
+    "application/rss+xml",
+    "application/atom+xml",
+    "application/feed+json",
+];
+
+pub struct FeedCandidate {
+    pub uri: String,
+    pub title: Option<String>,
+}
+
+pub enum Discovery {
+    /// The URI itself is a feed.
+    Feed(String, Box<Download>),
+    /// The URI is a web page advertising (or hosting) these feeds.
+    Candidates(Vec<FeedCandidate>),
+}
+
+/// Fetch `uri` and, if it is a web page rather than a feed, find the feeds it links to.
+pub async fn discover(client: &Client, uri: &str) -> Result<Discovery> {
+    let response = check_status(client.get(uri).send().await?)?;
+    let base = response.url().clone();
+    let headers = response.headers().clone();
+    let body = read_body(response, MAX_BODY_SIZE).await?;
+    let err = match Download::parse(&headers, &body) {
+        Ok(download) => return Ok(Discovery::Feed(uri.to_string(), Box::new(download))),
+        Err(err) => err,
+    };
+    let mut candidates = link_candidates(&String::from_utf8_lossy(&body), &base);
+    if candidates.is_empty() {
+        candidates = probe(client, &base).await;
+    }
+    if candidates.is_empty() {
+        return Err(err);
+    }
+    Ok(Discovery::Candidates(candidates))
+}
+
+/// Feeds advertised by `<link rel="alternate">` tags of an HTML page.
+fn link_candidates(html: &str, base: &Url) -> Vec<FeedCandidate> {
+    let document = Html::parse_document(html);
+    let base = Selector::parse("base[href]")
+        .ok()
+        .and_then(|selector| {
+            document
+                .select(&selector)
+                .next()
+                .and_then(|b| b.value().attr("href"))
+                .and_then(|href| base.join(href).ok())
+        })
+        .unwrap_or_else(|| base.clone());
+    let selector = Selector::parse("link[rel~=alternate][href][type]").unwrap();
+    let mut candidates: Vec<FeedCandidate> = Vec::new();
+    for link in document.select(&selector) {
+        let link = link.value();
+        let is_feed = link
+            .attr("type")
+            .map(|t| FEED_TYPES.contains(&t.trim().to_ascii_lowercase().as_str()))
+            .unwrap_or(false);
+        let uri = match link.attr("href").and_then(|href| base.join(href.trim()).ok()) {
+            Some(uri) if is_feed => uri.to_string(),
+            _ => continue,
+        };
+        if candidates.iter().all(|c| c.uri != uri) {
+            candidates.push(FeedCandidate {
+                uri,
+                title: link.attr("title").map(|t| t.to_string()),
+            });
+        }
+    }
+    candidates
+}
+
+/// Feeds found at common paths below the page and below the site root, by
+/// the URL they end up at after redirects.
+async fn probe(client: &Client, base: &Url) -> Vec<FeedCandidate> {
+    let page = base.as_str().trim_end_matches('/').to_string();
+    let mut uris: Vec<String> = Vec::new();
+    for path in COMMON_FEED_PATHS {
+        let below_page = format!("{}{}", page, path);
+        let below_root = base.join(path).map(|u| u.to_string()).ok();
+        for uri in std::iter::once(below_page).chain(below_root) {
+            if !uris.contains(&uri) {
+                uris.push(uri);
+            }
+        }
+    }
+    let found: Vec<Option<FeedCandidate>> = stream::iter(uris)
+        .map(|uri| async move {
+            let response = client.get(&uri).send().await.ok()?.error_for_status().ok()?;
+            let uri = response.url().to_string();
+            let body = read_body(response, MAX_BODY_SIZE).await.ok()?;
+            let feed = feed_rs::parser::parse(&body[..]).ok()?;
+            Some(FeedCandidate {
+                uri,
+                title: feed.title.map(|t| t.content),
+            })
+        })
+        .buffered(PROBE_CONCURRENCY)
+        .collect()
+        .await;
+    let mut candidates: Vec<FeedCandidate> = Vec::new();
+    for candidate in found.into_iter().flatten() {
+        if candidates.iter().all(|c| c.uri != candidate.uri) {
+            candidates.push(candidate);
+        }
+    }
+    candidates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidates(html: &str) -> Vec<(String, Option<String>)> {
+        let base = Url::parse("http://example.com/blog/post").unwrap();
+        link_candidates(html, &base)
+            .into_iter()
+            .map(|c| (c.uri, c.title))
+            .collect()
+    }
+
+    #[test]
+    fn finds_advertised_feeds() {
+        let html = r#"<html><head>
+            <link rel="alternate" type="application/rss+xml" title="Posts" href="/feed.xml">
+            <link rel="alternate" type=" Application/Atom+XML " href="atom.xml">
+            <link rel="alternate" type="text/html" href="/fr/">
+            <link rel="stylesheet" type="application/rss+xml" href="/not-a-feed">
+            <link rel="alternate" type="application/rss+xml" href="http://example.com/feed.xml">
+        </head></html>"#;
+        assert_eq!(
+            candidates(html),
+            [
+                ("http://example.com/feed.xml".to_string(), Some("Posts".to_string())),
+                ("http://example.com/blog/atom.xml".to_string(), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn resolves_against_the_base_element() {
+        let html = r#"<html><head>
+            <base href="http://cdn.example.com/site/">
+            <link rel="alternate feed" type="application/feed+json" href="feed.json">
+        </head></html>"#;
+        assert_eq!(
+            candidates(html),
+            [("http://cdn.example.com/site/feed.json".to_string(), None)]
+        );
+    }
+
+    #[test]
+    fn finds_nothing_on_plain_pages() {
+        assert!(candidates("<html><body><a href=\"/feed.xml\">Feed</a></body></html>").is_empty());
+    }
+}
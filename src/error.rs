@@ -1,15 +1,21 @@
-use std::fmt::write;
-
 use sqlx::Error as DBError;
 use reqwest::Error as ReqError;
 use feed_rs::parser::ParseFeedError;
-use handlebars::RenderError;
+use argon2::password_hash::Error as PasswordHashError;
+use opml::Error as OpmlError;
+use reqwest::StatusCode;
 
 #[derive(std::fmt::Debug)]
 pub enum AppError {
     DBError(DBError),
     UpdateError(ReqError),
-    FeedParseError(ParseFeedError)
+    FeedParseError(ParseFeedError),
+    PasswordHashError(PasswordHashError),
+    OpmlError(OpmlError),
+    /// Unsuccessful HTTP status, with the seconds to wait from `Retry-After` if any.
+    HttpError(StatusCode, Option<u64>),
+    /// Response body larger than the limit, in bytes.
+    TooLarge(usize)
 }
 
 pub type Result<T> = std::result::Result<T, AppError>;
@@ -19,11 +25,35 @@ impl std::fmt::Display for AppError {
         match self {
             Self::DBError(err) => write!(f, "{}", err),
             Self::UpdateError(err) => write!(f, "{}", err),
-            Self::FeedParseError(err) => write!(f, "{}", err)
+            Self::FeedParseError(err) => write!(f, "{}", err),
+            Self::PasswordHashError(err) => write!(f, "{}", err),
+            Self::OpmlError(err) => write!(f, "{}", err),
+            Self::HttpError(status, _) => write!(f, "HTTP {}", status),
+            Self::TooLarge(max) => write!(f, "Response larger than {} bytes", max)
         }
     }
 }
 
+impl AppError {
+    /// Short name of the kind of error, as recorded for failing feeds.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::DBError(_) => "database",
+            Self::UpdateError(_) => "network",
+            Self::FeedParseError(_) => "parse",
+            Self::PasswordHashError(_) => "password",
+            Self::OpmlError(_) => "opml",
+            Self::HttpError(..) => "http",
+            Self::TooLarge(_) => "size"
+        }
+    }
+
+    /// Whether a row that was looked up does not exist.
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, Self::DBError(DBError::RowNotFound))
+    }
+}
+
 impl std::error::Error for AppError {}
 
 impl From<DBError> for AppError {
@@ -42,4 +72,16 @@ impl From<ParseFeedError> for AppError {
     fn from(value: ParseFeedError) -> Self {
         Self::FeedParseError(value)
     }
+}
+
+impl From<PasswordHashError> for AppError {
+    fn from(value: PasswordHashError) -> Self {
+        Self::PasswordHashError(value)
+    }
+}
+
+impl From<OpmlError> for AppError {
+    fn from(value: OpmlError) -> Self {
+        Self::OpmlError(value)
+    }
 }
\ No newline at end of file
@@ -0,0 +1,143 @@
+use crate::error::Result;
+use crate::http::{check_status, read_body, MAX_BODY_SIZE};
+use scraper::{ElementRef, Html, Selector};
+use std::collections::HashMap;
+
+/// Parts of class and id names hinting at the main text of a page.
+const POSITIVE_HINTS: [&str; 10] = [
+    "article", "body", "content", "entry", "main", "page", "post", "text", "blog", "story",
+];
+/// Parts of class and id names hinting at page furniture.
+const NEGATIVE_HINTS: [&str; 12] = [
+    "comment", "meta", "footer", "footnote", "sidebar", "nav", "menu", "share", "related",
+    "promo", "widget", "banner",
+];
+/// Paragraphs shorter than this are not scored.
+const MIN_PARAGRAPH_LENGTH: usize = 25;
+/// Extractions shorter than this are treated as failures.
+const MIN_ARTICLE_LENGTH: usize = 250;
+
+/// Download `uri` and extract the HTML of its main article, if one stands out.
+pub async fn extract(client: &reqwest::Client, uri: &str) -> Result<Option<String>> {
+    let response = check_status(client.get(uri).send().await?)?;
+    let html = read_body(response, MAX_BODY_SIZE).await?;
+    Ok(article(&String::from_utf8_lossy(&html)))
+}
+
+/// Readability-style extraction: paragraphs give points to their parent and
+/// grandparent, adjusted by class and id names and by link density; the
+/// element with the best score is the article.
+fn article(html: &str) -> Option<String> {
+    let document = Html::parse_document(html);
+    let paragraphs = Selector::parse("p, pre, td").unwrap();
+    let mut scores = HashMap::new();
+    for paragraph in document.select(&paragraphs) {
+        let text = paragraph.text().collect::<String>();
+        let length = text.trim().chars().count();
+        if length < MIN_PARAGRAPH_LENGTH {
+            continue;
+        }
+        let score = 1.0 + text.matches(',').count() as f64 + (length as f64 / 100.0).min(3.0);
+        let parent = paragraph.parent().and_then(ElementRef::wrap);
+        let grandparent = parent.and_then(|p| p.parent()).and_then(ElementRef::wrap);
+        for (candidate, share) in [(parent, 1.0), (grandparent, 0.5)] {
+            if let Some(candidate) = candidate {
+                *scores
+                    .entry(candidate.id())
+                    .or_insert_with(|| class_weight(candidate)) += score * share;
+            }
+        }
+    }
+    let best = scores
+        .into_iter()
+        .filter_map(|(id, score)| {
+            let candidate = document.tree.get(id).and_then(ElementRef::wrap)?;
+            Some((candidate, score * (1.0 - link_density(candidate))))
+        })
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))?
+        .0;
+    if best.text().collect::<String>().trim().chars().count() < MIN_ARTICLE_LENGTH {
+        return None;
+    }
+    Some(best.html())
+}
+
+fn class_weight(element: ElementRef) -> f64 {
+    let names = format!(
+        "{} {}",
+        element.value().attr("class").unwrap_or_default(),
+        element.value().id().unwrap_or_default()
+    )
+    .to_lowercase();
+    let mut weight = 0.0;
+    if POSITIVE_HINTS.iter().any(|hint| names.contains(hint)) {
+        weight += 25.0;
+    }
+    if NEGATIVE_HINTS.iter().any(|hint| names.contains(hint)) {
+        weight -= 25.0;
+    }
+    match element.value().name() {
+        "article" | "main" => weight + 10.0,
+        "div" => weight + 5.0,
+        "form" | "aside" | "nav" | "footer" | "header" => weight - 10.0,
+        _ => weight,
+    }
+}
+
+/// Share of the text of `element` that sits inside links.
+fn link_density(element: ElementRef) -> f64 {
+    let links = Selector::parse("a").unwrap();
+    let length = element.text().map(|t| t.len()).sum::<usize>();
+    if length == 0 {
+        return 1.0;
+    }
+    let link_length = element
+        .select(&links)
+        .flat_map(|a| a.text())
+        .map(|t| t.len())
+        .sum::<usize>();
+    link_length as f64 / length as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PARAGRAPH: &str = "<p>Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod tempor incididunt ut labore et dolore magna aliqua.</p>";
+
+    #[test]
+    fn finds_the_article_among_page_furniture() {
+        let html = format!(
+            r#"<html><body>
+                <nav class="menu"><p>Home, About, Archive, Contact, Subscribe to the newsletter</p></nav>
+                <div class="post-content">{0}{0}{0}</div>
+                <div class="comments"><p>Great post, thanks for writing it, really enjoyed it.</p></div>
+            </body></html>"#,
+            PARAGRAPH
+        );
+        let article = article(&html).unwrap();
+        assert!(article.starts_with(r#"<div class="post-content">"#), "{}", article);
+        assert!(!article.contains("newsletter"));
+        assert!(!article.contains("Great post"));
+    }
+
+    #[test]
+    fn prefers_text_over_links() {
+        let links = format!("<a href=\"/a\">{}</a>", PARAGRAPH.replace("<p>", "").replace("</p>", ""));
+        let html = format!(
+            r#"<html><body>
+                <div class="content"><p>{0}</p><p>{0}</p><p>{0}</p></div>
+                <div class="content">{1}</div>
+            </body></html>"#,
+            links, PARAGRAPH.repeat(3)
+        );
+        let article = article(&html).unwrap();
+        assert!(!article.contains("<a href"), "{}", article);
+    }
+
+    #[test]
+    fn gives_up_on_short_pages() {
+        assert_eq!(article(&format!("<html><body><div>{}</div></body></html>", PARAGRAPH)), None);
+        assert_eq!(article("<html><body><p>Hello</p></body></html>"), None);
+    }
+}
@@ -1,46 +1,165 @@
-use crate::model::{feed::Feed, tag::Tag};
+use crate::model::{
+    feed::{Feed, Subscription},
+    icon::Icon,
+    item::Item, preference::StreamPreference, session::Session, stream::Stream,
+    tag::Tag,
+};
+use crate::opml::{self, ImportStatus};
 use crate::app::State;
-use actix_web::{web::Query, *};
+use crate::error::Result;
+use actix_web::*;
 use futures::future::join_all;
-use serde::Deserialize;
 use serde_json::json;
 use serde_json::Value as JsonValue;
 
-#[derive(Deserialize)]
-struct LoginRequest {
-    #[serde(rename = "Passwd")]
-    passwd: String,
+/// Register the GReader API below the current path: `/accounts/ClientLogin`
+/// and the `/reader/api/0` endpoints.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(login).service(
+        web::scope("/reader/api/0")
+            .service(token)
+            .service(user_info)
+            .service(preferences)
+            .service(stream_preferences)
+            .service(set_stream_preference)
+            .service(tags)
+            .service(feeds)
+            .service(export_feeds)
+            .service(import_feeds)
+            .service(add_feed)
+            .service(edit_feed)
+            .service(unread_count)
+            .service(get_items)
+            .service(get_item_by_id)
+            .service(get_stream_items)
+            .service(get_feed_items)
+            .service(edit_tag)
+            .service(mark_all_as_read)
+            .service(set_playback_position)
+            .service(rename_tag)
+            .service(remove_tag),
+    );
 }
 
-#[post("/accounts/ClientLogin")]
-pub async fn login(query: web::Query<LoginRequest>) -> impl Responder {
-    if true {
-        // TODO: Password protection
-        let session_token = "noauth"; // TODO: Authentication token
-        HttpResponse::Ok().body(format!("Auth={}\n", session_token))
-    } else {
-        HttpResponse::Forbidden().body("Password Wrong")
+#[route("/accounts/ClientLogin", method = "GET", method = "POST")]
+pub async fn login(req: HttpRequest, body: web::Bytes, state: web::Data<State<'_>>) -> impl Responder {
+    let params = helper::Params::from_request(&req, &body);
+    let (email, passwd) = match (params.get("Email"), params.get("Passwd")) {
+        (Some(email), Some(passwd)) => (email, passwd),
+        _ => return HttpResponse::BadRequest().body("Error=BadAuthentication\n"),
+    };
+    match Session::login(&state.pool, email, passwd).await {
+        Ok(Some(session_token)) => HttpResponse::Ok().body(format!(
+            "SID={}\nLSID=null\nAuth={}\n",
+            session_token, session_token
+        )),
+        Ok(None) => HttpResponse::Forbidden().body("Error=BadAuthentication\n"),
+        Err(_) => HttpResponse::InternalServerError().body("Error=Unknown\n"),
     }
 }
 
-#[get("/api/0/tag/list")]
+#[get("/token")]
+pub async fn token(req: HttpRequest, state: web::Data<State<'_>>) -> HttpResponse {
+    let auth_token = match helper::auth_token(&req) {
+        Some(auth_token) => auth_token,
+        None => return HttpResponse::Unauthorized().body("Authentication failed"),
+    };
+    if helper::check_token(&req, &state.pool).await.is_none() {
+        return HttpResponse::Unauthorized().body("Authentication failed");
+    }
+    match Session::issue_edit_token(&state.pool, auth_token).await {
+        Ok(edit_token) => HttpResponse::Ok().body(edit_token),
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
+}
+
+#[get("/user-info")]
+pub async fn user_info(req: HttpRequest, state: web::Data<State<'_>>) -> HttpResponse {
+    let session = match helper::check_token(&req, &state.pool).await {
+        Some(session) => session,
+        None => return HttpResponse::Unauthorized().body("Authentication failed"),
+    };
+    let response = json!({
+        "userId": session.id.to_string(),
+        "userName": session.email,
+        "userProfileId": session.id.to_string(),
+        "userEmail": session.email,
+        "isBloggerUser": false,
+        "isMultiLoginEnabled": false
+    });
+    HttpResponse::Ok().body(serde_json::to_string(&response).unwrap())
+}
+
+#[get("/preference/list")]
+pub async fn preferences(req: HttpRequest, state: web::Data<State<'_>>) -> HttpResponse {
+    if helper::check_token(&req, &state.pool).await.is_none() {
+        return HttpResponse::Unauthorized().body("Authentication failed");
+    }
+    HttpResponse::Ok().body(json!({ "prefs": [] }).to_string())
+}
+
+#[get("/preference/stream/list")]
+pub async fn stream_preferences(req: HttpRequest, state: web::Data<State<'_>>) -> HttpResponse {
+    let session = match helper::check_token(&req, &state.pool).await {
+        Some(session) => session,
+        None => return HttpResponse::Unauthorized().body("Authentication failed"),
+    };
+    let stored = match StreamPreference::preferences(&state.pool, &session).await {
+        Ok(stored) => stored,
+        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+    };
+    let mut streams = serde_json::Map::new();
+    for preference in stored {
+        let prefs = streams
+            .entry(preference.stream_id)
+            .or_insert_with(|| json!([]));
+        if let JsonValue::Array(prefs) = prefs {
+            prefs.push(json!({ "id": preference.key, "value": preference.value }));
+        }
+    }
+    HttpResponse::Ok().body(json!({ "streamprefs": streams }).to_string())
+}
+
+#[post("/preference/stream/set")]
+pub async fn set_stream_preference(
+    req: HttpRequest,
+    body: web::Bytes,
+    state: web::Data<State<'_>>,
+) -> HttpResponse {
+    let params = helper::Params::from_request(&req, &body);
+    let session = match helper::check_edit_token(&req, &state.pool, &params).await {
+        Some(session) => session,
+        None => return helper::bad_edit_token(),
+    };
+    let (stream_id, key, value) = match (params.get("s"), params.get("k"), params.get("v")) {
+        (Some(s), Some(k), Some(v)) => (s, k, v),
+        _ => return HttpResponse::BadRequest().body("Missing s, k or v"),
+    };
+    match StreamPreference::set(&state.pool, &session, stream_id, key, value).await {
+        Ok(()) => HttpResponse::Ok().body("OK"),
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
+}
+
+#[get("/tag/list")]
 pub async fn tags(req: HttpRequest, state: web::Data<State<'_>>) -> HttpResponse {
-    if !helper::check_token(&req).await {
-        return HttpResponse::Forbidden().body("Authentication failed");
+    if helper::check_token(&req, &state.pool).await.is_none() {
+        return HttpResponse::Unauthorized().body("Authentication failed");
     }
     let stared = json!({
         "id": "user/-/state/com.google/starred"
     });
     let mut tags = vec![stared];
-    Tag::tags(&state.pool)
+    Tag::unread_counts(&state.pool)
         .await
         .unwrap_or_default()
         .iter()
-        .for_each(|tag| {
+        .for_each(|(tag, count)| {
             tags.push(json!({
                 "id": format!("user/-/label/{}", tag.name),
+                "sortid": helper::tag_sortid(tag.id),
                 "type": "tag",
-                "unread_count": 0 // TODO: unread count of tag
+                "unread_count": count.count
             }))
         });
     let response = json!({
@@ -49,23 +168,30 @@ pub async fn tags(req: HttpRequest, state: web::Data<State<'_>>) -> HttpResponse
     HttpResponse::Ok().body(serde_json::to_string(&response).unwrap())
 }
 
-#[get("/api/0/subscription/list")]
+#[get("/subscription/list")]
 pub async fn feeds(req: HttpRequest, state: web::Data<State<'_>>) -> HttpResponse {
-    if !helper::check_token(&req).await {
-        return HttpResponse::Forbidden().body("Authentication failed");
+    if helper::check_token(&req, &state.pool).await.is_none() {
+        return HttpResponse::Unauthorized().body("Authentication failed");
     }
     let feeds = Feed::feeds(&state.pool).await.unwrap_or_default();
+    let icons = Icon::feed_ids(&state.pool).await.unwrap_or_default();
+    let origin = helper::origin(&req);
     let feeds_json = feeds.iter().map(|f| async {
         json!({
             "id": format!("feed/{}", f.id),
             "title": f.title,
+            "sortid": helper::feed_sortid(f.id),
             "categories": f.tags(&state.pool).await.unwrap_or_default().iter().map(|t| json!({
                 "id": format!("user/-/label/{}", t.name),
                 "label": t.name
             })).collect::<Vec<JsonValue>>(),
             "url": f.feed_uri,
             "htmlUrl": f.site_uri,
-            "iconUrl": null // TODO:  icon
+            "iconUrl": icons.contains(&f.id).then(|| format!("{}/icons/{}", origin, f.id)),
+            "lastError": f.last_error,
+            "lastErrorKind": f.last_error_kind,
+            "consecutiveFailures": f.consecutive_failures,
+            "lastSuccessTimestamp": f.last_success_at.map(|t| t.timestamp())
         })
     });
     let response = json!({
@@ -75,25 +201,90 @@ pub async fn feeds(req: HttpRequest, state: web::Data<State<'_>>) -> HttpRespons
 }
 
 #[get("/subscriptions/export")]
-pub async fn export_feeds(req: HttpRequest) -> HttpResponse {
-    unimplemented!()
-}
-
-#[post("/api/0/import/opml")]
-pub async fn import_feeds(req: HttpRequest) -> HttpResponse {
-    unimplemented!()
+pub async fn export_feeds(req: HttpRequest, state: web::Data<State<'_>>) -> HttpResponse {
+    if helper::check_token(&req, &state.pool).await.is_none() {
+        return HttpResponse::Unauthorized().body("Authentication failed");
+    }
+    match opml::export(&state.pool).await {
+        Ok(opml) => HttpResponse::Ok()
+            .content_type("text/xml")
+            .insert_header(("Content-Disposition", "attachment; filename=\"liu.opml\""))
+            .body(opml),
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
 }
 
-#[derive(Deserialize)]
-pub struct QuickAddQuery {
-    quickadd: String,
+#[post("/import/opml")]
+pub async fn import_feeds(
+    req: HttpRequest,
+    body: web::Bytes,
+    state: web::Data<State<'_>>,
+) -> HttpResponse {
+    // The document is either the whole body, with T in the query string, or
+    // the `opml` field of a form that also carries T.
+    let form = req.content_type() == "application/x-www-form-urlencoded";
+    let params = match form {
+        true => helper::Params::from_request(&req, &body),
+        false => helper::Params::from_query(&req),
+    };
+    if helper::check_edit_token(&req, &state.pool, &params)
+        .await
+        .is_none()
+    {
+        return helper::bad_edit_token();
+    }
+    let src = match form {
+        true => params.get("opml").unwrap_or_default().to_string(),
+        false => String::from_utf8_lossy(&body).into_owned(),
+    };
+    let results = match opml::import(&state.pool, &state.client, &src).await {
+        Ok(results) => results,
+        Err(err) => return HttpResponse::BadRequest().body(err.to_string()),
+    };
+    let report = results
+        .iter()
+        .map(|r| match &r.status {
+            ImportStatus::Imported(feed) => json!({
+                "url": r.uri,
+                "title": feed.title,
+                "status": "imported",
+                "streamId": format!("feed/{}", feed.id)
+            }),
+            ImportStatus::Exists => json!({
+                "url": r.uri,
+                "title": r.title,
+                "status": "exists"
+            }),
+            ImportStatus::Failed(err) => json!({
+                "url": r.uri,
+                "title": r.title,
+                "status": "failed",
+                "error": err
+            }),
+        })
+        .collect::<Vec<JsonValue>>();
+    HttpResponse::Ok().body(json!({ "feeds": report }).to_string())
 }
 
-#[post("/api/0/subscription/quickadd")]
-pub async fn add_feed(query: Query<QuickAddQuery>, state: web::Data<State<'_>>) -> HttpResponse {
-    let uri = query.quickadd.as_str();
-    match Feed::add_and_update_feed(&state.pool, &uri).await {
-        Ok(feed) => HttpResponse::Ok().body(
+#[post("/subscription/quickadd")]
+pub async fn add_feed(
+    req: HttpRequest,
+    body: web::Bytes,
+    state: web::Data<State<'_>>,
+) -> HttpResponse {
+    let params = helper::Params::from_request(&req, &body);
+    if helper::check_edit_token(&req, &state.pool, &params)
+        .await
+        .is_none()
+    {
+        return helper::bad_edit_token();
+    }
+    let uri = match params.get("quickadd") {
+        Some(uri) => uri.strip_prefix("feed/").unwrap_or(uri),
+        None => return HttpResponse::BadRequest().body("Missing quickadd"),
+    };
+    match Feed::subscribe(&state.pool, &state.client, uri).await {
+        Ok(Subscription::Subscribed(feed)) => HttpResponse::Ok().body(
             json!({
                 "numResults": 1,
                 "query": uri,
@@ -102,7 +293,20 @@ pub async fn add_feed(query: Query<QuickAddQuery>, state: web::Data<State<'_>>)
             })
             .to_string(),
         ),
-        Err(err) => {
+        Ok(Subscription::Choices(candidates)) => {
+            let results = candidates
+                .iter()
+                .map(|c| json!({ "streamId": format!("feed/{}", c.uri), "title": c.title }))
+                .collect::<Vec<JsonValue>>();
+            let response: JsonValue = json!({
+                "numResults": results.len(),
+                "query": uri,
+                "results": results,
+                "error": "Several feeds found, subscribe to one of them"
+            });
+            HttpResponse::Ok().body(response.to_string())
+        }
+        Err(_) => {
             let response: JsonValue = json!({
                 "numResults": 0,
                 "error": "Cannot retrieve feed source"
@@ -112,128 +316,885 @@ pub async fn add_feed(query: Query<QuickAddQuery>, state: web::Data<State<'_>>)
     }
 }
 
-#[derive(Deserialize)]
-#[serde(rename_all = "lowercase")]
 enum EditAction {
     Subscribe,
     Edit,
     Unsubscribe,
 }
 
-#[derive(Deserialize)]
-pub struct EditFeedQuery {
-    ac: EditAction,
-    s: String,         // URI
-    t: Option<String>, // Title
-    a: Option<String>, // Add tag
-    r: Option<String>, // Remove tag
+impl EditAction {
+    fn parse(ac: &str) -> Option<EditAction> {
+        match ac {
+            "subscribe" => Some(EditAction::Subscribe),
+            "edit" => Some(EditAction::Edit),
+            "unsubscribe" => Some(EditAction::Unsubscribe),
+            _ => None,
+        }
+    }
 }
 
-#[post("/api/0/subscription/edit")]
+#[post("/subscription/edit")]
 pub async fn edit_feed(
-    query: web::Query<EditFeedQuery>,
     req: HttpRequest,
+    body: web::Bytes,
     state: web::Data<State<'_>>,
 ) -> HttpResponse {
-    if !helper::check_token(&req).await {
-        return HttpResponse::Forbidden().body("Authentication failed");
+    let params = helper::Params::from_request(&req, &body);
+    if helper::check_edit_token(&req, &state.pool, &params)
+        .await
+        .is_none()
+    {
+        return helper::bad_edit_token();
     }
-    match query.ac {
-        EditAction::Subscribe => unimplemented!(),
-        EditAction::Edit => unimplemented!(),
-        EditAction::Unsubscribe => unimplemented!(),
+    let action = match params.get("ac").and_then(EditAction::parse) {
+        Some(action) => action,
+        None => return HttpResponse::BadRequest().body("Invalid action"),
+    };
+    let titles = params.get_all("t");
+    let add = params.get_all("a");
+    let remove = params.get_all("r");
+    // Clients batch folder moves as several `s` (and matching `t`) values.
+    for (n, stream_id) in params.get_all("s").iter().enumerate() {
+        let title = titles.get(n).copied();
+        if let Err(err) =
+            edit_subscription(&state, &action, stream_id, title, &add, &remove).await
+        {
+            return HttpResponse::InternalServerError().body(err.to_string());
+        }
     }
-    unimplemented!()
+    HttpResponse::Ok().body("OK")
 }
 
-#[get("/api/0/unread_count")]
+async fn edit_subscription(
+    state: &State<'_>,
+    action: &EditAction,
+    stream_id: &str,
+    title: Option<&str>,
+    add: &[&str],
+    remove: &[&str],
+) -> Result<()> {
+    let pool = &state.pool;
+    let mut feed = match action {
+        EditAction::Subscribe => {
+            let uri = stream_id.strip_prefix("feed/").unwrap_or(stream_id);
+            match Feed::get_feed_by_uri(pool, uri).await {
+                Ok(feed) => feed,
+                Err(_) => Feed::add_and_update_feed(pool, &state.client, uri).await?,
+            }
+        }
+        _ => helper::find_feed(pool, stream_id).await?,
+    };
+    if let EditAction::Unsubscribe = action {
+        return feed.delete(pool).await;
+    }
+    if let Some(title) = title.filter(|t| !t.is_empty()) {
+        feed.title = title.to_string();
+        feed.save(pool).await?;
+    }
+    for label in add {
+        if let Some(Stream::Label(name)) = helper::parse_stream(label) {
+            feed.add_tag(Tag::get_or_create(pool, &name).await?, pool)
+                .await?;
+        }
+    }
+    for label in remove {
+        if let Some(Stream::Label(name)) = helper::parse_stream(label) {
+            if let Ok(tag) = Tag::get_tag_by_name(pool, &name).await {
+                feed.remove_tag(tag, pool).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[get("/unread-count")]
 pub async fn unread_count(req: HttpRequest, state: web::Data<State<'_>>) -> HttpResponse {
-    if !helper::check_token(&req).await {
-        return HttpResponse::Forbidden().body("Authentication failed");
+    if helper::check_token(&req, &state.pool).await.is_none() {
+        return HttpResponse::Unauthorized().body("Authentication failed");
     }
     let pool = &state.pool;
-    let mut response: Vec<JsonValue> = Vec::new();
-    let mut all_count = 0;
-    for feed in Feed::feeds(pool).await.unwrap_or_default() {
-        let count = feed.unread_count(pool).await.unwrap_or(0);
-        all_count += count;
-        response.push(json!({
-            "id": format!("feed/{}", feed.id),
-            "count": count,
-            "newestItemTimestampUsec": "" // TODO: IDK
-        }));
-    }
-    for tag in Tag::tags(pool).await.unwrap_or_default() {
-        let count = tag.unread_count(pool).await.unwrap_or(0);
-        response.push(json!({
-            "id": format!("user/-/label/{}", tag.name),
-            "count": count,
-            "newestItemTimestampUsec": ""
-        }))
-    }
-    response.push(json!({
+    let feed_counts = match Feed::unread_counts(pool).await {
+        Ok(counts) => counts,
+        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+    };
+    let tag_counts = match Tag::unread_counts(pool).await {
+        Ok(counts) => counts,
+        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+    };
+    let all_count = match Item::unread_summary(pool).await {
+        Ok(count) => count,
+        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+    };
+    let counts = feed_counts
+        .into_iter()
+        .map(|(id, count)| (format!("feed/{}", id), count))
+        .chain(
+            tag_counts
+                .into_iter()
+                .map(|(tag, count)| (format!("user/-/label/{}", tag.name), count)),
+        )
+        .chain(std::iter::once((
+            "user/-/state/com.google/reading-list".to_string(),
+            all_count,
+        )))
+        .map(|(id, count)| {
+            json!({
+                "id": id,
+                "count": count.count,
+                "newestItemTimestampUsec": count
+                    .newest
+                    .map(|t| t.timestamp_micros().to_string())
+                    .unwrap_or_else(|| "0".to_string())
+            })
+        })
+        .collect::<Vec<JsonValue>>();
+    let response = json!({
+        "max": counts.len(),
+        "unreadcounts": counts
+    });
+    HttpResponse::Ok().body(serde_json::to_string(&response).unwrap())
+}
+
+#[route("/stream/items/ids", method = "GET", method = "POST")]
+pub async fn get_items(
+    req: HttpRequest,
+    body: web::Bytes,
+    state: web::Data<State<'_>>,
+) -> HttpResponse {
+    if helper::check_token(&req, &state.pool).await.is_none() {
+        return HttpResponse::Unauthorized().body("Authentication failed");
+    }
+    let params = helper::Params::from_request(&req, &body);
+    let stream_id = match params.get("s") {
+        Some(stream_id) => stream_id,
+        None => return HttpResponse::BadRequest().body("Missing s"),
+    };
+    let filter = match helper::stream_filter(stream_id, &params) {
+        Ok(filter) => filter,
+        Err(err) => return HttpResponse::BadRequest().body(err),
+    };
+    let (refs, continuation) = match helper::stream_page(&state.pool, filter).await {
+        Ok(page) => page,
+        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+    };
+    let item_refs = refs
+        .iter()
+        .map(|r| {
+            json!({
+                "id": r.id.to_string(),
+                "directStreamIds": [format!("feed/{}", r.feed_id)],
+                "timestampUsec": r.crawled_at.timestamp_micros().to_string()
+            })
+        })
+        .collect::<Vec<JsonValue>>();
+    let mut response = json!({ "itemRefs": item_refs });
+    if let Some(continuation) = continuation {
+        response["continuation"] = json!(continuation);
+    }
+    HttpResponse::Ok().body(serde_json::to_string(&response).unwrap())
+}
+
+#[route("/stream/items/contents", method = "GET", method = "POST")]
+pub async fn get_item_by_id(
+    req: HttpRequest,
+    body: web::Bytes,
+    state: web::Data<State<'_>>,
+) -> HttpResponse {
+    if helper::check_token(&req, &state.pool).await.is_none() {
+        return HttpResponse::Unauthorized().body("Authentication failed");
+    }
+    let params = helper::Params::from_request(&req, &body);
+    let ids = match params
+        .get_all("i")
+        .iter()
+        .map(|i| helper::parse_item_id(i))
+        .collect::<Option<Vec<i64>>>()
+    {
+        Some(ids) => ids,
+        None => return HttpResponse::BadRequest().body("Invalid item id"),
+    };
+    let items = match helper::items_json(&state.pool, &ids, &state.proxy, &helper::origin(&req)).await {
+        Ok(items) => items,
+        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+    };
+    let response = json!({
         "id": "user/-/state/com.google/reading-list",
-        "count": all_count,
-        "newestItemTimestampUsec": ""
-    }));
+        "updated": chrono::Utc::now().timestamp(),
+        "items": items
+    });
     HttpResponse::Ok().body(serde_json::to_string(&response).unwrap())
 }
 
-#[get("/api/0/stream/items/ids")]
-pub async fn get_items(req: HttpRequest) -> HttpResponse {
-    unimplemented!()
+/// `/stream/contents` with the stream given as `s`, as some clients send it.
+#[route("/stream/contents", method = "GET", method = "POST")]
+pub async fn get_stream_items(
+    req: HttpRequest,
+    body: web::Bytes,
+    state: web::Data<State<'_>>,
+) -> HttpResponse {
+    stream_contents("", &req, &body, &state).await
 }
 
-#[get("/api/0/stream/items/contents")]
-pub async fn get_item_by_id(req: HttpRequest) -> HttpResponse {
-    unimplemented!()
+#[route("/stream/contents/{stream:.*}", method = "GET", method = "POST")]
+pub async fn get_feed_items(
+    stream_id: web::Path<String>,
+    req: HttpRequest,
+    body: web::Bytes,
+    state: web::Data<State<'_>>,
+) -> HttpResponse {
+    stream_contents(&stream_id, &req, &body, &state).await
 }
 
-#[get("/api/0/stream/contents")]
-pub async fn get_feed_items(req: HttpRequest) -> HttpResponse {
-    unimplemented!()
+/// Items of `stream_id`, or when it is empty of the `s` parameter (the reading list by default).
+async fn stream_contents(stream_id: &str, req: &HttpRequest, body: &[u8], state: &State<'_>) -> HttpResponse {
+    if helper::check_token(req, &state.pool).await.is_none() {
+        return HttpResponse::Unauthorized().body("Authentication failed");
+    }
+    let pool = &state.pool;
+    let params = helper::Params::from_request(req, body);
+    let stream_id = match stream_id {
+        "" => params.get("s").unwrap_or("user/-/state/com.google/reading-list"),
+        s => s,
+    };
+    let filter = match helper::stream_filter(stream_id, &params) {
+        Ok(filter) => filter,
+        Err(err) => return HttpResponse::BadRequest().body(err),
+    };
+    let title = match helper::stream_title(pool, &filter.stream).await {
+        Ok(title) => title,
+        Err(err) if err.is_not_found() => return HttpResponse::NotFound().body("Stream not found"),
+        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+    };
+    let (refs, continuation) = match helper::stream_page(pool, filter).await {
+        Ok(page) => page,
+        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+    };
+    let ids = refs.iter().map(|r| r.id).collect::<Vec<i64>>();
+    let items = match helper::items_json(pool, &ids, &state.proxy, &helper::origin(req)).await {
+        Ok(items) => items,
+        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+    };
+    let mut response = json!({
+        "direction": "ltr",
+        "id": stream_id,
+        "title": title,
+        "updated": chrono::Utc::now().timestamp(),
+        "items": items
+    });
+    if let Some(continuation) = continuation {
+        response["continuation"] = json!(continuation);
+    }
+    HttpResponse::Ok().body(serde_json::to_string(&response).unwrap())
 }
 
-#[post("/api/0/edit-tag")]
-pub async fn editTag(req: HttpRequest) -> HttpResponse {
-    unimplemented!()
+#[post("/edit-tag")]
+pub async fn edit_tag(
+    req: HttpRequest,
+    body: web::Bytes,
+    state: web::Data<State<'_>>,
+) -> HttpResponse {
+    let params = helper::Params::from_request(&req, &body);
+    if helper::check_edit_token(&req, &state.pool, &params)
+        .await
+        .is_none()
+    {
+        return helper::bad_edit_token();
+    }
+    let ids = match params
+        .get_all("i")
+        .iter()
+        .map(|i| helper::parse_item_id(i))
+        .collect::<Option<Vec<i64>>>()
+    {
+        Some(ids) => ids,
+        None => return HttpResponse::BadRequest().body("Invalid item id"),
+    };
+    let mut add = Vec::new();
+    let mut remove = Vec::new();
+    for (targets, set) in [(params.get_all("a"), true), (params.get_all("r"), false)] {
+        for target in targets {
+            // kept-unread is the inverse of read.
+            let kept_unread = target
+                .strip_prefix("user/")
+                .and_then(|path| path.split_once('/'))
+                .map(|(_, path)| path == "state/com.google/kept-unread")
+                .unwrap_or(false);
+            let (stream, set) = match kept_unread {
+                true => (Some(Stream::Read), !set),
+                false => (helper::parse_stream(target), set),
+            };
+            match stream {
+                Some(stream @ (Stream::Read | Stream::Starred | Stream::Label(_))) => {
+                    if set {
+                        add.push(stream)
+                    } else {
+                        remove.push(stream)
+                    }
+                }
+                _ => return HttpResponse::BadRequest().body("Invalid tag"),
+            }
+        }
+    }
+    match Item::edit_tags(&state.pool, &ids, &add, &remove).await {
+        Ok(()) => HttpResponse::Ok().body("OK"),
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
 }
 
-#[get("/api/0/mark-all-as-read")]
-pub async fn markRead(req: HttpRequest) -> HttpResponse {
-    unimplemented!()
+/// Not part of GReader: remember where playback of an item's enclosure was left,
+/// `position` being in seconds. Clients read it back as `playbackPosition` of the item.
+#[post("/playback-position")]
+pub async fn set_playback_position(
+    req: HttpRequest,
+    body: web::Bytes,
+    state: web::Data<State<'_>>,
+) -> HttpResponse {
+    let params = helper::Params::from_request(&req, &body);
+    if helper::check_edit_token(&req, &state.pool, &params)
+        .await
+        .is_none()
+    {
+        return helper::bad_edit_token();
+    }
+    let id = match params.get("i").and_then(helper::parse_item_id) {
+        Some(id) => id,
+        None => return HttpResponse::BadRequest().body("Invalid item id"),
+    };
+    let position = match params.get("position").and_then(|p| p.parse::<f64>().ok()) {
+        Some(position) if position.is_finite() && position >= 0.0 => position,
+        _ => return HttpResponse::BadRequest().body("Invalid position"),
+    };
+    let mut item = match Item::get_item_by_id(&state.pool, id).await {
+        Ok(item) => item,
+        Err(_) => return HttpResponse::NotFound().body("Item not found"),
+    };
+    match item.set_playback_position(&state.pool, position).await {
+        Ok(()) => HttpResponse::Ok().body("OK"),
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
 }
 
-#[get("/api/0/rename-tag")]
-pub async fn renameTag(req: HttpRequest) -> HttpResponse {
-    unimplemented!()
+#[post("/mark-all-as-read")]
+pub async fn mark_all_as_read(
+    req: HttpRequest,
+    body: web::Bytes,
+    state: web::Data<State<'_>>,
+) -> HttpResponse {
+    let params = helper::Params::from_request(&req, &body);
+    if helper::check_edit_token(&req, &state.pool, &params)
+        .await
+        .is_none()
+    {
+        return helper::bad_edit_token();
+    }
+    let pool = &state.pool;
+    // Items that arrived after the client's snapshot stay unread.
+    let before = match params.get("ts") {
+        Some(ts) => match ts.parse().ok().and_then(helper::from_usec) {
+            Some(before) => before,
+            None => return HttpResponse::BadRequest().body("Invalid timestamp"),
+        },
+        None => chrono::Utc::now(),
+    };
+    let result = match params.get("s").and_then(helper::parse_stream) {
+        Some(Stream::Feed(id)) => match Feed::get_feed_by_id(pool, id).await {
+            Ok(feed) => feed.mark_read(pool, before).await,
+            Err(err) => Err(err),
+        },
+        Some(Stream::Label(name)) => match Tag::get_tag_by_name(pool, &name).await {
+            Ok(tag) => tag.mark_read(pool, before).await,
+            Err(err) => Err(err),
+        },
+        Some(Stream::ReadingList) => Item::mark_all_read(pool, before).await,
+        _ => return HttpResponse::BadRequest().body("Invalid stream"),
+    };
+    match result {
+        Ok(()) => HttpResponse::Ok().body("OK"),
+        Err(err) if err.is_not_found() => HttpResponse::NotFound().body("Stream not found"),
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
 }
 
-#[get("/api/0/disable-tag")]
-pub async fn remove_tag(req: HttpRequest) -> HttpResponse {
-    unimplemented!()
+#[post("/rename-tag")]
+pub async fn rename_tag(
+    req: HttpRequest,
+    body: web::Bytes,
+    state: web::Data<State<'_>>,
+) -> HttpResponse {
+    let params = helper::Params::from_request(&req, &body);
+    if helper::check_edit_token(&req, &state.pool, &params)
+        .await
+        .is_none()
+    {
+        return helper::bad_edit_token();
+    }
+    let (name, dest) = match (helper::label_param(&params), params.get("dest")) {
+        (Some(name), Some(dest)) => match helper::parse_stream(dest) {
+            Some(Stream::Label(dest)) => (name, dest),
+            _ => return HttpResponse::BadRequest().body("Invalid destination"),
+        },
+        _ => return HttpResponse::BadRequest().body("Invalid label"),
+    };
+    let result = match Tag::get_tag_by_name(&state.pool, &name).await {
+        Ok(tag) => tag.rename(&state.pool, &dest).await.map(|_| ()),
+        Err(err) => Err(err),
+    };
+    match result {
+        Ok(()) => HttpResponse::Ok().body("OK"),
+        Err(err) if err.is_not_found() => HttpResponse::NotFound().body("Label not found"),
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
+}
+
+#[post("/disable-tag")]
+pub async fn remove_tag(
+    req: HttpRequest,
+    body: web::Bytes,
+    state: web::Data<State<'_>>,
+) -> HttpResponse {
+    let params = helper::Params::from_request(&req, &body);
+    if helper::check_edit_token(&req, &state.pool, &params)
+        .await
+        .is_none()
+    {
+        return helper::bad_edit_token();
+    }
+    let name = match helper::label_param(&params) {
+        Some(name) => name,
+        None => return HttpResponse::BadRequest().body("Invalid label"),
+    };
+    let result = match Tag::get_tag_by_name(&state.pool, &name).await {
+        Ok(tag) => tag.delete(&state.pool).await,
+        Err(err) => Err(err),
+    };
+    match result {
+        Ok(()) => HttpResponse::Ok().body("OK"),
+        Err(err) if err.is_not_found() => HttpResponse::NotFound().body("Label not found"),
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
 }
 
 mod helper {
-    use actix_web::HttpRequest;
-
-    pub async fn check_token(req: &HttpRequest) -> bool {
-        let auth_token = req.headers().get("Authorization").map(|value| {
-            value
-                .as_bytes()
-                .strip_prefix(b"GoogleLogin auth=")
-                .unwrap_or_default()
-        });
-        match auth_token {
-            Some(token) => {
-                if true {
-                    // TODO: authentication
-                    return true;
-                } else {
-                    return false;
-                }
+    use crate::error::Result;
+    use crate::model::{enclosure::Enclosure, feed::Feed, item::Item, session::Session};
+    use crate::model::stream::{ItemRef, Stream, StreamFilter};
+    use crate::proxy::Proxy;
+    use actix_web::{HttpRequest, HttpResponse};
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+    use chrono::{DateTime, TimeZone, Utc};
+    use serde_json::{json, Value as JsonValue};
+    use sqlx::PgPool;
+    use std::collections::HashMap;
+
+    const ITEM_ID_PREFIX: &str = "tag:google.com,2005:reader/item/";
+
+    /// Most items a single stream request may ask for.
+    const MAX_STREAM_COUNT: i64 = 10000;
+
+    pub fn auth_token(req: &HttpRequest) -> Option<&str> {
+        req.headers()
+            .get("Authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("GoogleLogin auth="))
+            .map(|token| token.trim())
+    }
+
+    pub async fn check_token(req: &HttpRequest, pool: &PgPool) -> Option<Session> {
+        let auth_token = auth_token(req)?;
+        Session::get_session_by_token(pool, auth_token)
+            .await
+            .ok()
+            .flatten()
+    }
+
+    /// Authenticate a mutating request: a valid auth token plus a `T` edit token issued for it.
+    pub async fn check_edit_token(
+        req: &HttpRequest,
+        pool: &PgPool,
+        params: &Params,
+    ) -> Option<Session> {
+        let session = check_token(req, pool).await?;
+        let edit_token = params.get("T")?;
+        match Session::check_edit_token(pool, auth_token(req)?, edit_token).await {
+            Ok(true) => Some(session),
+            _ => None,
+        }
+    }
+
+    pub fn bad_edit_token() -> HttpResponse {
+        HttpResponse::Unauthorized()
+            .insert_header(("X-Reader-Google-Bad-Token", "true"))
+            .body("Authentication failed")
+    }
+
+    /// Request parameters, keeping repeated keys such as `i` or `xt`.
+    pub struct Params(Vec<(String, String)>);
+
+    impl Params {
+        pub fn from_query(req: &HttpRequest) -> Params {
+            Params(
+                url::form_urlencoded::parse(req.query_string().as_bytes())
+                    .into_owned()
+                    .collect(),
+            )
+        }
+
+        pub fn from_body(body: &[u8]) -> Params {
+            Params(url::form_urlencoded::parse(body).into_owned().collect())
+        }
+
+        /// Query string parameters followed by those of a form-encoded body.
+        pub fn from_request(req: &HttpRequest, body: &[u8]) -> Params {
+            let mut params = Self::from_query(req);
+            params.0.extend(Self::from_body(body).0);
+            params
+        }
+
+        pub fn get(&self, key: &str) -> Option<&str> {
+            self.0
+                .iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, v)| v.as_str())
+        }
+
+        pub fn get_all(&self, key: &str) -> Vec<&str> {
+            self.0
+                .iter()
+                .filter(|(k, _)| k == key)
+                .map(|(_, v)| v.as_str())
+                .collect()
+        }
+    }
+
+    /// Parse a stream id such as `feed/1`, `user/-/label/News` or
+    /// `user/-/state/com.google/reading-list`.
+    pub fn parse_stream(id: &str) -> Option<Stream> {
+        if let Some(feed_id) = id.strip_prefix("feed/") {
+            return feed_id.parse().ok().map(Stream::Feed);
+        }
+        let (_, path) = id.strip_prefix("user/")?.split_once('/')?;
+        if let Some(name) = path.strip_prefix("label/") {
+            return Some(Stream::Label(name.to_string()));
+        }
+        match path {
+            "state/com.google/reading-list" => Some(Stream::ReadingList),
+            "state/com.google/starred" => Some(Stream::Starred),
+            "state/com.google/read" => Some(Stream::Read),
+            _ => None,
+        }
+    }
+
+    /// Build a filter over `stream_id` from the `n`, `r`, `ot`, `nt`, `xt`,
+    /// `it`, `includeAllDirectSubdirectories` and `c` parameters, or name the
+    /// parameter that is invalid.
+    pub fn stream_filter(stream_id: &str, params: &Params) -> std::result::Result<StreamFilter, &'static str> {
+        let mut filter = StreamFilter::new(parse_stream(stream_id).ok_or("Invalid stream")?);
+        if let Some(n) = params.get("n") {
+            filter.count = n.parse::<i64>().map_err(|_| "Invalid n")?.clamp(1, MAX_STREAM_COUNT);
+        }
+        filter.oldest_first = params.get("r") == Some("o");
+        if let Some(ot) = params.get("ot") {
+            filter.start_time = Some(parse_timestamp(ot).ok_or("Invalid ot")?);
+        }
+        if let Some(nt) = params.get("nt") {
+            filter.end_time = Some(parse_timestamp(nt).ok_or("Invalid nt")?);
+        }
+        for target in params.get_all("xt") {
+            match parse_stream(target) {
+                Some(Stream::Read) => filter.read = Some(false),
+                Some(Stream::Starred) => filter.star = Some(false),
+                _ => {}
             }
-            None => return false,
+        }
+        for target in params.get_all("it") {
+            match parse_stream(target) {
+                Some(Stream::Read) => filter.read = Some(true),
+                Some(Stream::Starred) => filter.star = Some(true),
+                _ => {}
+            }
+        }
+        filter.include_subdirectories = params
+            .get("includeAllDirectSubdirectories")
+            .map(|v| v == "true" || v == "1")
+            .unwrap_or(false);
+        if let Some(c) = params.get("c").filter(|c| !c.is_empty()) {
+            filter.continuation = Some(decode_continuation(c).ok_or("Invalid continuation")?);
+        }
+        Ok(filter)
+    }
+
+    /// A time given in seconds since the epoch.
+    fn parse_timestamp(seconds: &str) -> Option<DateTime<Utc>> {
+        Utc.timestamp_opt(seconds.parse().ok()?, 0).single()
+    }
+
+    /// One page of `filter`, with the continuation for the next page if there is one.
+    pub async fn stream_page(
+        pool: &PgPool,
+        mut filter: StreamFilter,
+    ) -> Result<(Vec<ItemRef>, Option<String>)> {
+        let count = filter.count;
+        // Fetch one extra item to tell whether another page follows.
+        filter.count += 1;
+        let mut refs = filter.item_refs(pool).await?;
+        let continuation = if refs.len() as i64 > count {
+            refs.truncate(count as usize);
+            refs.last().map(encode_continuation)
+        } else {
+            None
         };
+        Ok((refs, continuation))
+    }
+
+    pub async fn stream_title(pool: &PgPool, stream: &Stream) -> Result<String> {
+        Ok(match stream {
+            Stream::Feed(id) => Feed::get_feed_by_id(pool, *id).await?.title,
+            Stream::Label(name) => name.clone(),
+            Stream::ReadingList => "Reading list".to_string(),
+            Stream::Starred => "Starred".to_string(),
+            Stream::Read => "Read".to_string(),
+        })
+    }
+
+    pub fn from_usec(usec: i64) -> Option<DateTime<Utc>> {
+        Utc.timestamp_opt(
+            usec.div_euclid(1_000_000),
+            (usec.rem_euclid(1_000_000) * 1000) as u32,
+        )
+        .single()
+    }
+
+    /// Label named by `s` (`user/-/label/<name>`) or by `t` (`<name>`).
+    pub fn label_param(params: &Params) -> Option<String> {
+        match params.get("s").map(parse_stream) {
+            Some(Some(Stream::Label(name))) => Some(name),
+            Some(_) => None,
+            None => params.get("t").map(|t| t.to_string()),
+        }
+    }
+
+    /// Sort ids used in `subscription-ordering`. Tags get the top bit set so
+    /// they never collide with feeds.
+    pub fn feed_sortid(id: i64) -> String {
+        format!("{:08X}", id & 0x7fff_ffff)
+    }
+
+    pub fn tag_sortid(id: i64) -> String {
+        format!("{:08X}", 0x8000_0000 | (id & 0x7fff_ffff))
+    }
+
+    /// The feed addressed by `feed/<id>` or `feed/<feed uri>`.
+    pub async fn find_feed(pool: &PgPool, stream_id: &str) -> Result<Feed> {
+        let feed = stream_id.strip_prefix("feed/").unwrap_or(stream_id);
+        match feed.parse() {
+            Ok(id) => Feed::get_feed_by_id(pool, id).await,
+            Err(_) => Feed::get_feed_by_uri(pool, feed).await,
+        }
+    }
+
+    /// Pages are ordered by arrival time, then id; the continuation is the
+    /// position of the last item of a page.
+    pub fn encode_continuation(item: &ItemRef) -> String {
+        URL_SAFE_NO_PAD.encode(format!("{}:{}", item.crawled_at.timestamp_micros(), item.id))
+    }
+
+    pub fn decode_continuation(continuation: &str) -> Option<(DateTime<Utc>, i64)> {
+        let decoded = String::from_utf8(URL_SAFE_NO_PAD.decode(continuation).ok()?).ok()?;
+        let (usec, id) = decoded.split_once(':')?;
+        Some((from_usec(usec.parse().ok()?)?, id.parse().ok()?))
+    }
+
+    /// Parse an item id given in decimal, as 16 hex digits, or in long form
+    /// (`tag:google.com,2005:reader/item/<hex>`).
+    pub fn parse_item_id(id: &str) -> Option<i64> {
+        if let Some(hex) = id.strip_prefix(ITEM_ID_PREFIX) {
+            return u64::from_str_radix(hex, 16).ok().map(|id| id as i64);
+        }
+        if id.len() == 16 && id.chars().all(|c| c.is_ascii_hexdigit()) {
+            return u64::from_str_radix(id, 16).ok().map(|id| id as i64);
+        }
+        id.parse().ok()
+    }
+
+    pub fn long_item_id(id: i64) -> String {
+        format!("{}{:016x}", ITEM_ID_PREFIX, id)
+    }
+
+    /// Scheme and host the client reached this server at, for absolute proxy URLs.
+    pub fn origin(req: &HttpRequest) -> String {
+        let info = req.connection_info();
+        format!("{}://{}", info.scheme(), info.host())
+    }
+
+    pub fn item_json(
+        item: &Item,
+        feed: Option<&Feed>,
+        labels: &[String],
+        enclosures: &[Enclosure],
+        proxy: &Proxy,
+        origin: &str,
+    ) -> JsonValue {
+        let mut categories = vec!["user/-/state/com.google/reading-list".to_string()];
+        if item.read {
+            categories.push("user/-/state/com.google/read".to_string());
+        }
+        if item.star {
+            categories.push("user/-/state/com.google/starred".to_string());
+        }
+        categories.extend(labels.iter().map(|name| format!("user/-/label/{}", name)));
+        // Categories of the entry itself are passed through as plain tags.
+        categories.extend(item.categories.iter().cloned());
+        let links = item
+            .link
+            .iter()
+            .map(|link| json!({"href": link, "type": "text/html"}))
+            .collect::<Vec<JsonValue>>();
+        let enclosures = enclosures
+            .iter()
+            .map(|e| {
+                json!({
+                    "href": e.url,
+                    "type": e.mime_type,
+                    "length": e.length.map(|l| l.to_string()),
+                    "duration": e.duration,
+                    "image": e.image.as_ref().map(|image| proxy.url(origin, image))
+                })
+            })
+            .collect::<Vec<JsonValue>>();
+        // Most clients only read `summary`, so like FreshRSS it carries the
+        // whole body, extracted article included, rather than the feed's teaser.
+        let content = proxy.rewrite(origin, &item.body_html());
+        json!({
+            "id": long_item_id(item.id),
+            "crawlTimeMsec": item.crawled_at.timestamp_millis().to_string(),
+            "timestampUsec": item.crawled_at.timestamp_micros().to_string(),
+            "published": item.created_at.timestamp(),
+            "updated": item.updated_at.timestamp(),
+            "title": item.title,
+            "canonical": links,
+            "alternate": links,
+            "summary": {
+                "direction": "ltr",
+                "content": content
+            },
+            "content": {
+                "direction": "ltr",
+                "content": content
+            },
+            "originId": item.guid,
+            "thumbnail": item.thumbnail.as_ref().map(|url| json!({"url": proxy.url(origin, url)})),
+            "enclosure": enclosures,
+            "playbackPosition": item.playback_position,
+            "author": item.author,
+            "origin": {
+                "streamId": format!("feed/{}", item.feed_id),
+                "title": feed.map(|f| f.title.as_str()),
+                "htmlUrl": feed.and_then(|f| f.site_uri.as_deref())
+            },
+            "categories": categories
+        })
+    }
+
+    /// GReader JSON of the items with the given ids, in the same order.
+    pub async fn items_json(pool: &PgPool, ids: &[i64], proxy: &Proxy, origin: &str) -> Result<Vec<JsonValue>> {
+        let mut items = Item::get_items_by_ids(pool, ids).await?;
+        let mut feed_ids = items.iter().map(|i| i.feed_id).collect::<Vec<i64>>();
+        feed_ids.sort_unstable();
+        feed_ids.dedup();
+        let feeds = Feed::get_feeds_by_ids(pool, &feed_ids)
+            .await?
+            .into_iter()
+            .map(|f| (f.id, f))
+            .collect::<HashMap<i64, Feed>>();
+        let labels = Item::labels(pool, ids).await?;
+        let enclosures = Enclosure::enclosures(pool, ids).await?;
+        let order = ids
+            .iter()
+            .enumerate()
+            .map(|(n, id)| (*id, n))
+            .collect::<HashMap<i64, usize>>();
+        items.sort_by_key(|i| order.get(&i.id).copied());
+        Ok(items
+            .iter()
+            .map(|i| {
+                item_json(
+                    i,
+                    feeds.get(&i.feed_id),
+                    labels.get(&i.id).map(|l| l.as_slice()).unwrap_or_default(),
+                    enclosures.get(&i.id).map(|e| e.as_slice()).unwrap_or_default(),
+                    proxy,
+                    origin,
+                )
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::helper::*;
+    use crate::model::stream::{ItemRef, Stream};
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn parses_stream_ids() {
+        assert!(matches!(parse_stream("feed/12"), Some(Stream::Feed(12))));
+        assert!(matches!(parse_stream("user/-/label/News/Tech"), Some(Stream::Label(name)) if name == "News/Tech"));
+        assert!(matches!(parse_stream("user/1005/state/com.google/reading-list"), Some(Stream::ReadingList)));
+        assert!(matches!(parse_stream("user/-/state/com.google/starred"), Some(Stream::Starred)));
+        assert!(parse_stream("feed/http://example.com/feed").is_none());
+        assert!(parse_stream("user/-/state/com.google/broadcast").is_none());
+    }
+
+    #[test]
+    fn builds_stream_filters() {
+        let params = Params::from_body(b"n=50&r=o&ot=1700000000&xt=user/-/state/com.google/read");
+        let filter = stream_filter("user/-/state/com.google/reading-list", &params).unwrap();
+        assert_eq!(filter.count, 50);
+        assert!(filter.oldest_first);
+        assert_eq!(filter.start_time, Utc.timestamp_opt(1700000000, 0).single());
+        assert_eq!(filter.read, Some(false));
+        assert_eq!(filter.star, None);
+    }
+
+    #[test]
+    fn names_the_invalid_parameter() {
+        let filter = |query: &str| stream_filter("feed/1", &Params::from_body(query.as_bytes())).err();
+        assert_eq!(stream_filter("nonsense", &Params::from_body(b"")).err(), Some("Invalid stream"));
+        assert_eq!(filter("n=ten"), Some("Invalid n"));
+        assert_eq!(filter("ot=yesterday"), Some("Invalid ot"));
+        assert_eq!(filter("nt=1.5"), Some("Invalid nt"));
+        assert_eq!(filter("c=%%%"), Some("Invalid continuation"));
+        assert_eq!(filter("n=0"), None);
+    }
+
+    #[test]
+    fn round_trips_continuations() {
+        let item = ItemRef {
+            id: 42,
+            feed_id: 1,
+            crawled_at: Utc.timestamp_opt(1700000000, 123_456_000).unwrap(),
+        };
+        let continuation = encode_continuation(&item);
+        assert_eq!(decode_continuation(&continuation), Some((item.crawled_at, 42)));
+        assert_eq!(decode_continuation("NDI"), None);
+    }
+
+    #[test]
+    fn parses_item_ids() {
+        assert_eq!(parse_item_id("42"), Some(42));
+        assert_eq!(parse_item_id("000000000000002a"), Some(42));
+        assert_eq!(parse_item_id("tag:google.com,2005:reader/item/000000000000002a"), Some(42));
+        // Hex ids are unsigned, so the top half maps back onto negative ids.
+        assert_eq!(parse_item_id("ffffffffffffffff"), Some(-1));
+        assert_eq!(parse_item_id("-1"), Some(-1));
+        assert_eq!(parse_item_id("tag:google.com,2005:reader/item/xyz"), None);
+        assert_eq!(parse_item_id("item"), None);
+    }
+
+    #[test]
+    fn long_item_ids_parse_back() {
+        assert_eq!(long_item_id(42), "tag:google.com,2005:reader/item/000000000000002a");
+        assert_eq!(parse_item_id(&long_item_id(123456789)), Some(123456789));
     }
 }
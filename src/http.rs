@@ -0,0 +1,226 @@
+use crate::error::{AppError, Result};
+use chrono::{offset, DateTime, Utc};
+use hyper::client::connect::dns::Name;
+use reqwest::dns::{Addrs, Resolve, Resolving};
+use reqwest::redirect::{Attempt, Policy};
+use reqwest::{header, StatusCode, Url};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+/// Seconds allowed for connecting to a server.
+const CONNECT_TIMEOUT: u64 = 10;
+/// Seconds allowed for a whole request, body included.
+const TIMEOUT: u64 = 30;
+/// Largest feed or web page read.
+pub const MAX_BODY_SIZE: usize = 8 * 1024 * 1024;
+/// Redirects followed by the client for public URLs.
+const MAX_REDIRECTS: usize = 10;
+
+/// The client used for every outgoing request, with timeouts so that a
+/// stalled server cannot hold up a fetch.
+pub fn client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .connect_timeout(Duration::from_secs(CONNECT_TIMEOUT))
+        .timeout(Duration::from_secs(TIMEOUT))
+        .build()
+        .expect("HTTP client configuration is valid")
+}
+
+/// The client for URLs taken from feed content, which only connects to public
+/// addresses, after redirects too, so that feeds cannot make this server
+/// reach itself or its network.
+pub fn public_client() -> reqwest::Client {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+    CLIENT
+        .get_or_init(|| {
+            reqwest::Client::builder()
+                .connect_timeout(Duration::from_secs(CONNECT_TIMEOUT))
+                .timeout(Duration::from_secs(TIMEOUT))
+                .no_proxy()
+                .dns_resolver(Arc::new(PublicResolver))
+                .redirect(Policy::custom(follow_public))
+                .build()
+                .expect("HTTP client configuration is valid")
+        })
+        .clone()
+}
+
+fn follow_public(attempt: Attempt) -> reqwest::redirect::Action {
+    if attempt.previous().len() >= MAX_REDIRECTS {
+        attempt.error("too many redirects")
+    } else if !is_public_url(attempt.url()) {
+        attempt.error("redirect to a non-public address")
+    } else {
+        attempt.follow()
+    }
+}
+
+/// Resolves host names like the system does, dropping non-public addresses.
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addrs = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|addr| is_public(addr.ip()))
+                .collect::<Vec<SocketAddr>>();
+            if addrs.is_empty() {
+                return Err(format!("{} has no public address", name.as_str()).into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Whether `url` is http(s) and, if its host is an IP address, a public one.
+/// Host names are checked once resolved.
+pub fn is_public_url(url: &Url) -> bool {
+    let host = match url.host() {
+        Some(url::Host::Ipv4(ip)) => is_public(ip.into()),
+        Some(url::Host::Ipv6(ip)) => is_public(ip.into()),
+        Some(url::Host::Domain(_)) => true,
+        None => false,
+    };
+    matches!(url.scheme(), "http" | "https") && host
+}
+
+/// Whether `ip` is on the public internet rather than this host, a local
+/// network or a reserved range.
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                || a == 0
+                || a >= 240
+                // Shared address space (carrier-grade NAT)
+                || (a == 100 && (64..128).contains(&b))
+                // IETF protocol assignments
+                || (a == 192 && b == 0 && c == 0)
+                // Benchmarking
+                || (a == 198 && (18..20).contains(&b)))
+        }
+        IpAddr::V6(ip) => {
+            let segments = ip.segments();
+            // IPv4-mapped and NAT64 addresses lead to the IPv4 address inside.
+            if let Some(v4) = ip.to_ipv4_mapped() {
+                return is_public(v4.into());
+            }
+            if segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+                let [.., a, b, c, d] = ip.octets();
+                return is_public(Ipv4Addr::new(a, b, c, d).into());
+            }
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                // Unique local
+                || (segments[0] & 0xfe00) == 0xfc00
+                // Link-local
+                || (segments[0] & 0xffc0) == 0xfe80
+                // Documentation
+                || (segments[0] == 0x2001 && segments[1] == 0xdb8))
+        }
+    }
+}
+
+/// `response` if successful, or else an error with its status and the
+/// delay asked for by `Retry-After`.
+pub fn check_status(response: reqwest::Response) -> Result<reqwest::Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let retry_after = match status {
+        StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE => response
+            .headers()
+            .get(header::RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_retry_after),
+        _ => None,
+    };
+    Err(AppError::HttpError(status, retry_after))
+}
+
+/// Seconds to wait according to a `Retry-After` value, either delay-seconds or an HTTP date.
+fn parse_retry_after(value: &str) -> Option<u64> {
+    if let Ok(seconds) = value.trim().parse() {
+        return Some(seconds);
+    }
+    let date = DateTime::parse_from_rfc2822(value.trim()).ok()?;
+    let seconds = (date.with_timezone(&Utc) - offset::Utc::now()).num_seconds();
+    Some(seconds.max(0) as u64)
+}
+
+/// Body of `response`, failing once it grows past `max` bytes.
+pub async fn read_body(mut response: reqwest::Response, max: usize) -> Result<Vec<u8>> {
+    if response.content_length().unwrap_or(0) > max as u64 {
+        return Err(AppError::TooLarge(max));
+    }
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        body.extend_from_slice(&chunk);
+        if body.len() > max {
+            return Err(AppError::TooLarge(max));
+        }
+    }
+    Ok(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_delay_seconds() {
+        assert_eq!(parse_retry_after("120"), Some(120));
+        assert_eq!(parse_retry_after(" 0 "), Some(0));
+    }
+
+    #[test]
+    fn parses_http_dates() {
+        let later = (offset::Utc::now() + chrono::Duration::hours(1)).to_rfc2822();
+        let seconds = parse_retry_after(&later).unwrap();
+        assert!((3590..=3600).contains(&seconds), "{}", seconds);
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), Some(0));
+    }
+
+    #[test]
+    fn rejects_other_values() {
+        assert_eq!(parse_retry_after(""), None);
+        assert_eq!(parse_retry_after("-5"), None);
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+
+    #[test]
+    fn tells_public_addresses() {
+        for ip in ["93.184.216.34", "2606:2800:220:1:248:1893:25c8:1946", "::ffff:93.184.216.34"] {
+            assert!(is_public(ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in [
+            "127.0.0.1", "10.1.2.3", "172.16.0.1", "192.168.1.1", "169.254.169.254", "0.0.0.0",
+            "100.64.0.1", "255.255.255.255", "::1", "::", "fe80::1", "fd00::1", "::ffff:127.0.0.1",
+            "64:ff9b::a9fe:a9fe",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{}", ip);
+        }
+    }
+
+    #[test]
+    fn tells_public_urls() {
+        let public = |url: &str| is_public_url(&Url::parse(url).unwrap());
+        assert!(public("https://example.com/a.png"));
+        assert!(public("http://93.184.216.34/"));
+        assert!(!public("http://127.0.0.1:8080/"));
+        assert!(!public("http://[::1]/"));
+        assert!(!public("http://169.254.169.254/latest/meta-data/"));
+        assert!(!public("file:///etc/passwd"));
+    }
+}
@@ -1,5 +1,12 @@
+pub mod discovery;
 pub mod error;
+pub mod extract;
 pub mod model;
 pub mod app;
 pub mod site;
-pub mod greader;
\ No newline at end of file
+pub mod greader;
+pub mod http;
+pub mod opml;
+pub mod proxy;
+pub mod sanitize;
+pub mod scheduler;
@@ -2,36 +2,139 @@ use actix_web::*;
 use liu_feed::app::State;
 use liu_feed::site;
 use liu_feed::greader;
+use liu_feed::http;
+use liu_feed::model::{feed::Feed, item::Item};
+use liu_feed::model::session::{random_token, Session};
+use liu_feed::proxy::{Proxy, ProxyMode};
+use liu_feed::scheduler::Scheduler;
 use sqlx::PgPool;
+use std::time::Duration;
+use tokio::sync::watch;
+
+/// Minutes between fetches of a feed, unless overridden per feed.
+const DEFAULT_FETCH_INTERVAL: i64 = 30;
+/// Feeds fetched at once by the scheduler.
+const DEFAULT_FETCH_CONCURRENCY: usize = 4;
+/// Where the image proxy keeps downloaded files.
+const DEFAULT_PROXY_CACHE: &str = "cache/proxy";
+/// Megabytes the proxy cache may take up.
+const DEFAULT_PROXY_CACHE_SIZE: u64 = 1024;
+/// How often the proxy cache is trimmed to its size.
+const PROXY_PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
 
 #[tokio::main]
 async fn main() -> std::io::Result<()> {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
     let pool = PgPool::connect("postgres:liu-feed").await.unwrap();
+    let args: Vec<String> = std::env::args().collect();
+    match args.get(1).map(|s| s.as_str()) {
+        Some("passwd") => return set_password(&pool, args.get(2)).await,
+        Some("sanitize") => return sanitize_stored(&pool).await,
+        _ => {}
+    }
     let mut hb = handlebars::Handlebars::new();
     hb.register_templates_directory(".html", "static").unwrap();
-    HttpServer::new(move || {
+    let client = http::client();
+    let scheduler = Scheduler {
+        pool: pool.clone(),
+        client: client.clone(),
+        interval: env_or("LIU_FETCH_INTERVAL", DEFAULT_FETCH_INTERVAL) * 60,
+        concurrency: env_or("LIU_FETCH_CONCURRENCY", DEFAULT_FETCH_CONCURRENCY),
+    };
+    // Without a fixed key, proxy URLs handed out before a restart stop working.
+    let proxy_key = std::env::var("LIU_PROXY_KEY").unwrap_or_else(|_| random_token());
+    let proxy = Proxy::new(
+        env_or("LIU_PROXY", ProxyMode::Off),
+        proxy_key.as_bytes(),
+        env_or("LIU_PROXY_CACHE", DEFAULT_PROXY_CACHE.to_string()),
+    );
+    let cache_size = env_or("LIU_PROXY_CACHE_SIZE", DEFAULT_PROXY_CACHE_SIZE) * 1024 * 1024;
+    let pruned = proxy.clone();
+    tokio::spawn(async move {
+        loop {
+            if let Err(err) = pruned.prune(cache_size).await {
+                log::warn!("Failed to prune the proxy cache: {}", err);
+            }
+            tokio::time::sleep(PROXY_PRUNE_INTERVAL).await;
+        }
+    });
+    let (shutdown, shutdown_rx) = watch::channel(false);
+    let scheduler = tokio::spawn(scheduler.run(shutdown_rx));
+    let server = HttpServer::new(move || {
         let data = web::Data::new(State {
             pool: pool.clone(),
             hb: hb.clone(),
+            proxy: proxy.clone(),
+            client: client.clone(),
         });
-        let reader_api = web::scope("")
-            .guard(guard::Header("content-type", "application/json"))
-            .app_data(data.clone())
-            .service(greader::login)
-            .service(greader::tags);
-        let site = web::scope("").app_data(data)
+        // GReader clients either use the standard paths or the FreshRSS-style prefix.
+        let reader_api = web::scope("/api/greader.php").configure(greader::configure);
+        let site = web::scope("")
+            .service(site::login_page)
+            .service(site::login)
+            .service(site::logout)
             .service(site::index)
             .service(site::create_feed)
             .service(site::get_feed)
             .service(site::get_item)
+            .service(site::extract_item)
+            .service(site::set_playback_position)
             .service(site::update_feed)
-            .service(site::delete_feed);
+            .service(site::mark_feed_read)
+            .service(site::set_fetch_interval)
+            .service(site::set_full_text)
+            .service(site::delete_feed)
+            .service(site::export_opml)
+            .service(site::import_opml)
+            .service(site::get_icon)
+            .service(site::proxied)
+            .service(site::proxied_media);
         App::new()
+            .app_data(data)
             .wrap(middleware::Compress::default())
             .service(reader_api)
+            .configure(greader::configure)
             .service(site)
     })
     .bind(("0.0.0.0", 8080))?
     .run()
-    .await
+    .await;
+    let _ = shutdown.send(true);
+    let _ = scheduler.await;
+    server
+}
+
+/// Value of the environment variable `name`, or `default` when unset or invalid.
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    std::env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+/// `liu-feed passwd <email>` reads a password from stdin and stores it for GReader logins.
+async fn set_password(pool: &PgPool, email: Option<&String>) -> std::io::Result<()> {
+    let email = match email {
+        Some(email) => email,
+        None => {
+            eprintln!("usage: liu-feed passwd <email>");
+            return Ok(());
+        }
+    };
+    let mut password = String::new();
+    std::io::stdin().read_line(&mut password)?;
+    let password = password.trim_end_matches(['\r', '\n']);
+    Session::create(pool, email, password)
+        .await
+        .map_err(std::io::Error::other)?;
+    println!("Password updated for {}", email);
+    Ok(())
+}
+
+/// `liu-feed sanitize` cleans what earlier versions stored with the current rules.
+async fn sanitize_stored(pool: &PgPool) -> std::io::Result<()> {
+    let items = Item::sanitize_all(pool).await.map_err(std::io::Error::other)?;
+    let feeds = Feed::sanitize_all(pool).await.map_err(std::io::Error::other)?;
+    println!("Sanitized {} items and {} feeds", items, feeds);
+    Ok(())
 }
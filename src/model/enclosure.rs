@@ -0,0 +1,78 @@
+use crate::error::Result;
+use sqlx::*;
+use std::collections::HashMap;
+
+/// A media file attached to an item, such as a podcast episode.
+#[derive(FromRow)]
+pub struct Enclosure {
+    pub id: i64,
+    pub item_id: i64,
+    pub url: String,
+    pub mime_type: Option<String>,
+    /// Size in bytes.
+    pub length: Option<i64>,
+    /// Duration in seconds.
+    pub duration: Option<i64>,
+    pub image: Option<String>,
+}
+
+/// An enclosure as read from a feed entry, before it is stored.
+pub struct NewEnclosure {
+    pub url: String,
+    pub mime_type: Option<String>,
+    pub length: Option<i64>,
+    pub duration: Option<i64>,
+    pub image: Option<String>,
+}
+
+impl Enclosure {
+    /// Enclosures of each item, keyed by item id.
+    pub async fn enclosures(pool: &PgPool, item_ids: &[i64]) -> Result<HashMap<i64, Vec<Enclosure>>> {
+        let rows = query_as!(
+            Enclosure,
+            "select id, item_id, url, mime_type, length, duration, image from enclosures where item_id = any($1) order by id",
+            item_ids
+        )
+        .fetch_all(pool)
+        .await?;
+        let mut enclosures: HashMap<i64, Vec<Enclosure>> = HashMap::new();
+        for enclosure in rows {
+            enclosures.entry(enclosure.item_id).or_default().push(enclosure);
+        }
+        Ok(enclosures)
+    }
+
+    /// Make the enclosures of the item match `new`. Returns whether anything changed.
+    pub async fn sync(pool: &PgPool, item_id: i64, new: &[NewEnclosure]) -> Result<bool> {
+        let urls = new.iter().map(|e| e.url.clone()).collect::<Vec<String>>();
+        let mut tx = pool.begin().await?;
+        let mut changed = query!(
+            "delete from enclosures where item_id = $1 and url <> all($2)",
+            item_id,
+            &urls
+        )
+        .execute(&mut *tx)
+        .await?
+        .rows_affected()
+            > 0;
+        for enclosure in new {
+            changed |= query!(
+                "insert into enclosures (item_id, url, mime_type, length, duration, image) values ($1, $2, $3, $4, $5, $6)
+                on conflict (item_id, url) do update set mime_type = excluded.mime_type, length = excluded.length, duration = excluded.duration, image = excluded.image
+                where (enclosures.mime_type, enclosures.length, enclosures.duration, enclosures.image) is distinct from (excluded.mime_type, excluded.length, excluded.duration, excluded.image)",
+                item_id,
+                enclosure.url,
+                enclosure.mime_type,
+                enclosure.length,
+                enclosure.duration,
+                enclosure.image
+            )
+            .execute(&mut *tx)
+            .await?
+            .rows_affected()
+                > 0;
+        }
+        tx.commit().await?;
+        Ok(changed)
+    }
+}
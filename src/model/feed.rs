@@ -1,8 +1,32 @@
-use crate::error::Result;
-use crate::model::{item::Item, tag::Tag};
-use chrono::{offset, DateTime, Utc};
-use md5::Digest;
+use crate::discovery::{discover, Discovery, FeedCandidate};
+use crate::error::{AppError, Result};
+use crate::http::{check_status, read_body, MAX_BODY_SIZE};
+use crate::sanitize::{http_url, resolve, sanitize};
+use crate::model::{
+    enclosure::NewEnclosure,
+    icon::Icon,
+    item::{Item, NewItem, Upsert},
+    stream::UnreadCount,
+    tag::Tag,
+};
+use chrono::{offset, DateTime, Duration, Utc};
+use futures::{stream, StreamExt};
+use reqwest::{header, Client, StatusCode};
+use siphasher::sip128::{Hasher128, SipHasher};
 use sqlx::*;
+use std::hash::Hasher;
+use url::Url;
+
+/// Longest a failing feed is backed off, in seconds.
+const MAX_BACKOFF: i64 = 24 * 60 * 60;
+/// Articles of new items downloaded at once for full-text feeds.
+const EXTRACT_CONCURRENCY: usize = 4;
+
+pub enum Subscription {
+    Subscribed(Box<Feed>),
+    /// The page links to several feeds; the user has to pick one.
+    Choices(Vec<FeedCandidate>),
+}
 
 #[derive(sqlx::FromRow)]
 pub struct Feed {
@@ -11,6 +35,56 @@ pub struct Feed {
     pub feed_uri: String,
     pub site_uri: Option<String>,
     pub updated_at: DateTime<Utc>,
+    pub last_fetched_at: Option<DateTime<Utc>>,
+    pub next_fetch_at: DateTime<Utc>,
+    /// Seconds between fetches, overriding the scheduler's default.
+    pub fetch_interval: Option<i64>,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub last_success_at: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+    pub last_error_kind: Option<String>,
+    pub consecutive_failures: i32,
+    /// Extract the full article of new items from their links.
+    pub full_text: bool,
+}
+
+/// How many entries of a fetched feed were new, changed or already stored.
+#[derive(Default)]
+pub struct UpdateSummary {
+    pub inserted: usize,
+    pub updated: usize,
+    pub unchanged: usize,
+}
+
+/// A downloaded feed, with the validators for fetching it conditionally next time.
+pub struct Download {
+    pub feed: feed_rs::model::Feed,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+impl Download {
+    /// Parse `body`, keeping the validators of the response it came with.
+    pub fn parse(headers: &header::HeaderMap, body: &[u8]) -> Result<Download> {
+        let validator = |name| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.to_string())
+        };
+        Ok(Download {
+            feed: feed_rs::parser::parse(body)?,
+            etag: validator(header::ETAG),
+            last_modified: validator(header::LAST_MODIFIED),
+        })
+    }
+}
+
+/// Outcome of a conditional fetch.
+pub enum FetchResult {
+    NotModified,
+    Modified(Box<Download>),
 }
 
 impl Feed {
@@ -22,11 +96,21 @@ impl Feed {
         updated_at: DateTime<Utc>,
     ) -> Feed {
         Feed {
-            id: id,
+            id,
             title: title.to_string(),
             feed_uri: feed_uri.to_string(),
             site_uri: site_uri.map(|s| s.to_string()),
-            updated_at: updated_at,
+            updated_at,
+            last_fetched_at: None,
+            next_fetch_at: updated_at,
+            fetch_interval: None,
+            etag: None,
+            last_modified: None,
+            last_success_at: None,
+            last_error: None,
+            last_error_kind: None,
+            consecutive_failures: 0,
+            full_text: false,
         }
     }
 
@@ -48,42 +132,109 @@ impl Feed {
         feed: &feed_rs::model::Feed,
     ) -> Result<Feed> {
         let title = feed.title.clone().map(|t| t.content).unwrap_or_default();
-        let site_uri = feed.links.first().map(|l| l.href.as_str());
-        Self::create(pool, &title, uri, site_uri).await
+        let site_uri = alternate(&feed.links).and_then(|l| http_url(Url::parse(uri).ok().as_ref(), &l.href));
+        Self::create(pool, &title, uri, site_uri.as_deref()).await
     }
 
     pub async fn create_item_from_entry(
         &self,
         pool: &PgPool,
         entry: &feed_rs::model::Entry,
-    ) -> Result<()> {
-        let content = entry
-            .content
-            .clone()
-            .map(|c| c.body.unwrap_or_default())
-            .unwrap_or_default();
-        let link = entry.links.first().map(|l| l.href.as_str());
-        let title = entry.title.clone().map(|t| t.content).unwrap_or_default();
+    ) -> Result<Upsert> {
+        let site = self.base_url();
+        let link = alternate(&entry.links).and_then(|l| http_url(site.as_ref(), &l.href));
+        // Relative URLs in the content are relative to its xml:base, or else to the item page.
+        let page = link.as_deref().and_then(|l| Url::parse(l).ok()).or(site.clone());
+        let xml_base = xml_base(entry);
+        let summary = entry
+            .summary
+            .as_ref()
+            .map(|s| sanitize(&s.content, page.as_ref()));
+        // Feeds that only carry a description (most RSS) have it as the summary.
+        let (content, content_type) = match (&entry.content, &entry.summary) {
+            (Some(feed_rs::model::Content { body: Some(body), content_type, .. }), _) => {
+                (body.clone(), Some(content_type.to_string()))
+            }
+            (_, Some(summary)) => (summary.content.clone(), Some(summary.content_type.to_string())),
+            _ => (String::new(), None),
+        };
+        // Plain text is escaped wherever it is shown instead.
+        let content = match &content_type {
+            Some(t) if t.starts_with("text/plain") => content,
+            _ => sanitize(&content, xml_base.as_ref().or(page.as_ref())),
+        };
         let author = entry
             .authors
             .iter()
             .fold(String::new(), |acc, p| format!("{}, {}", acc, p.name));
-        let created_at = entry.published.unwrap_or_default();
-        let updated_at = entry.updated.unwrap_or_default();
-        let _ = Item::create(
-            pool, self.id, link, &title, &author, &content, created_at, updated_at,
-        )
-        .await;
-        Ok(())
+        let categories = entry
+            .categories
+            .iter()
+            .map(|c| c.label.clone().unwrap_or_else(|| c.term.clone()))
+            .collect();
+        let new = NewItem {
+            guid: guid(entry),
+            link,
+            title: entry.title.clone().map(|t| t.content).unwrap_or_default(),
+            author,
+            content,
+            summary,
+            content_type,
+            categories,
+            thumbnail: thumbnail(entry).and_then(|t| http_url(site.as_ref(), &t)),
+            enclosures: enclosures(entry, site.as_ref()),
+            created_at: entry.published.unwrap_or_default(),
+            updated_at: entry.updated.unwrap_or_default(),
+        };
+        Item::upsert(pool, self.id, &new).await
+    }
+
+    /// Store the articles at the links of new items, a few at a time. A
+    /// failed extraction leaves the feed's own content in place.
+    async fn extract_items(&self, pool: &PgPool, ids: &[i64]) {
+        let items = match Item::get_items_by_ids(pool, ids).await {
+            Ok(items) => items,
+            Err(err) => {
+                log::warn!("Failed to load new items of {}: {}", self.feed_uri, err);
+                return;
+            }
+        };
+        stream::iter(items)
+            .for_each_concurrent(EXTRACT_CONCURRENCY, |mut item| async move {
+                if let Err(err) = item.extract_content(pool).await {
+                    log::warn!("Failed to extract {:?}: {}", item.link, err);
+                }
+            })
+            .await;
+    }
+
+    /// The URL relative links of the feed are resolved against: its site, or else the feed itself.
+    fn base_url(&self) -> Option<Url> {
+        let feed_uri = Url::parse(&self.feed_uri).ok();
+        match &self.site_uri {
+            Some(site_uri) => Url::parse(&resolve(feed_uri.as_ref(), site_uri)).ok(),
+            None => feed_uri,
+        }
+    }
+
+    /// Drop site links that are not http(s), as stored by earlier versions.
+    /// Returns how many feeds changed.
+    pub async fn sanitize_all(pool: &PgPool) -> Result<u64> {
+        let changed = query!("update feeds set site_uri = null where site_uri !~* '^https?://'")
+            .execute(pool)
+            .await?
+            .rows_affected();
+        Ok(changed)
     }
 
     pub async fn save(&self, pool: &PgPool) -> Result<()> {
         query!(
-            "update feeds set title = $1, feed_uri = $2, site_uri = $3, updated_at = $4",
+            "update feeds set title = $1, feed_uri = $2, site_uri = $3, updated_at = $4 where id = $5",
             self.title,
             self.feed_uri,
             self.site_uri,
-            self.updated_at
+            self.updated_at,
+            self.id
         )
         .execute(pool)
         .await?;
@@ -98,7 +249,7 @@ impl Feed {
     }
 
     pub async fn items(&self, pool: &PgPool) -> Result<Vec<Item>> {
-        let items = query_as!(Item, "select id, feed_id, hash, link, title, author, content, created_at, updated_at, read, star from items where feed_id = $1 order by updated_at desc", self.id)
+        let items = query_as!(Item, "select id, feed_id, guid, hash, link, title, author, content, extracted_content, summary, content_type, categories, thumbnail, created_at, updated_at, crawled_at, read, star, playback_position from items where feed_id = $1 order by updated_at desc", self.id)
         .fetch_all(pool).await?;
         Ok(items)
     }
@@ -110,7 +261,18 @@ impl Feed {
 
     pub async fn add_tag(&self, tag: Tag, pool: &PgPool) -> Result<()> {
         query!(
-            "insert into taggings (feed_id, tag_id) values ($1, $2)",
+            "insert into taggings (feed_id, tag_id) values ($1, $2) on conflict do nothing",
+            self.id,
+            tag.id
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn remove_tag(&self, tag: Tag, pool: &PgPool) -> Result<()> {
+        query!(
+            "delete from taggings where feed_id = $1 and tag_id = $2",
             self.id,
             tag.id
         )
@@ -130,10 +292,113 @@ impl Feed {
         Ok(count)
     }
 
+    /// Mark read every item of this feed that arrived before `before`.
+    pub async fn mark_read(&self, pool: &PgPool, before: DateTime<Utc>) -> Result<()> {
+        query!(
+            "update items set read = true where feed_id = $1 and read = false and crawled_at <= $2",
+            self.id,
+            before
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Unread count of every feed, keyed by feed id.
+    pub async fn unread_counts(pool: &PgPool) -> Result<Vec<(i64, UnreadCount)>> {
+        let counts = query!(
+            "select feeds.id, count(items.id) filter (where items.read = false) as \"count!\", max(items.crawled_at) as newest
+            from feeds left join items on items.feed_id = feeds.id group by feeds.id"
+        )
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .map(|row| {
+            (
+                row.id,
+                UnreadCount {
+                    count: row.count,
+                    newest: row.newest,
+                },
+            )
+        })
+        .collect();
+        Ok(counts)
+    }
+
+    /// Feeds whose next fetch is due, given the default interval in seconds.
+    /// Fetching a feed by hand also postpones its next scheduled fetch.
+    pub async fn due_feeds(pool: &PgPool, default_interval: i64) -> Result<Vec<Feed>> {
+        let feeds = query_as!(
+            Feed,
+            "select id, title, feed_uri, site_uri, updated_at, last_fetched_at, next_fetch_at, fetch_interval, etag, last_modified, last_success_at, last_error, last_error_kind, consecutive_failures, full_text from feeds
+            where greatest(next_fetch_at, last_fetched_at + coalesce(fetch_interval, $1) * interval '1 second') <= now()
+            order by next_fetch_at",
+            default_interval
+        )
+        .fetch_all(pool)
+        .await?;
+        Ok(feeds)
+    }
+
+    /// Schedule the next fetch one interval from now, doubling the interval
+    /// for every consecutive failure. A later `Retry-After` of the last
+    /// failure is kept.
+    pub async fn schedule_fetch(&mut self, pool: &PgPool, default_interval: i64) -> Result<()> {
+        let mut interval = self.fetch_interval.unwrap_or(default_interval);
+        if self.consecutive_failures > 0 {
+            let factor = 1i64 << (self.consecutive_failures - 1).min(16);
+            interval = interval.saturating_mul(factor).min(MAX_BACKOFF.max(interval));
+        }
+        let now = offset::Utc::now();
+        let next_fetch_at = now + Duration::seconds(interval);
+        self.next_fetch_at = match self.consecutive_failures > 0 && self.next_fetch_at > now {
+            true => self.next_fetch_at.max(next_fetch_at),
+            false => next_fetch_at,
+        };
+        query!(
+            "update feeds set next_fetch_at = $1 where id = $2",
+            self.next_fetch_at,
+            self.id
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn set_full_text(&mut self, pool: &PgPool, full_text: bool) -> Result<()> {
+        query!(
+            "update feeds set full_text = $1 where id = $2",
+            full_text,
+            self.id
+        )
+        .execute(pool)
+        .await?;
+        self.full_text = full_text;
+        Ok(())
+    }
+
+    /// Override the fetch interval of this feed; `None` restores the default.
+    /// The next fetch moves to one new interval after the last one.
+    pub async fn set_fetch_interval(&mut self, pool: &PgPool, interval: Option<i64>) -> Result<()> {
+        let next_fetch_at = self.last_fetched_at.unwrap_or_else(offset::Utc::now);
+        query!(
+            "update feeds set fetch_interval = $1, next_fetch_at = $2 where id = $3",
+            interval,
+            next_fetch_at,
+            self.id
+        )
+        .execute(pool)
+        .await?;
+        self.fetch_interval = interval;
+        self.next_fetch_at = next_fetch_at;
+        Ok(())
+    }
+
     pub async fn feeds(pool: &PgPool) -> Result<Vec<Feed>> {
         let feeds = query_as!(
             Feed,
-            "select id, title, feed_uri, site_uri, updated_at from feeds"
+            "select id, title, feed_uri, site_uri, updated_at, last_fetched_at, next_fetch_at, fetch_interval, etag, last_modified, last_success_at, last_error, last_error_kind, consecutive_failures, full_text from feeds"
         )
         .fetch_all(pool)
         .await?;
@@ -143,7 +408,7 @@ impl Feed {
     pub async fn get_feed_by_id(pool: &PgPool, id: i64) -> Result<Feed> {
         let feed = query_as!(
             Feed,
-            "select id, title, feed_uri, site_uri, updated_at from feeds where id = $1",
+            "select id, title, feed_uri, site_uri, updated_at, last_fetched_at, next_fetch_at, fetch_interval, etag, last_modified, last_success_at, last_error, last_error_kind, consecutive_failures, full_text from feeds where id = $1",
             id
         )
         .fetch_one(pool)
@@ -151,10 +416,21 @@ impl Feed {
         Ok(feed)
     }
 
+    pub async fn get_feeds_by_ids(pool: &PgPool, ids: &[i64]) -> Result<Vec<Feed>> {
+        let feeds = query_as!(
+            Feed,
+            "select id, title, feed_uri, site_uri, updated_at, last_fetched_at, next_fetch_at, fetch_interval, etag, last_modified, last_success_at, last_error, last_error_kind, consecutive_failures, full_text from feeds where id = any($1)",
+            ids
+        )
+        .fetch_all(pool)
+        .await?;
+        Ok(feeds)
+    }
+
     pub async fn get_feed_by_uri(pool: &PgPool, uri: &str) -> Result<Feed> {
         let feed = query_as!(
             Feed,
-            "select id, title, feed_uri, site_uri, updated_at from feeds where feed_uri = $1",
+            "select id, title, feed_uri, site_uri, updated_at, last_fetched_at, next_fetch_at, fetch_interval, etag, last_modified, last_success_at, last_error, last_error_kind, consecutive_failures, full_text from feeds where feed_uri = $1",
             uri
         )
         .fetch_one(pool)
@@ -166,36 +442,365 @@ impl Feed {
         Self::create_from_feed(pool, uri, feed).await
     }
 
-    pub async fn add_feed_from_uri(pool: &PgPool, uri: &str) -> Result<Feed> {
-        let feed = get_feed(uri).await?;
-        Self::add_feed(pool, uri, &feed).await
+    pub async fn add_feed_from_uri(pool: &PgPool, client: &Client, uri: &str) -> Result<Feed> {
+        let download = get_feed(client, uri).await?;
+        Self::add_feed(pool, uri, &download.feed).await
+    }
+
+    pub async fn add_and_update_feed(pool: &PgPool, client: &Client, uri: &str) -> Result<Feed> {
+        let download = get_feed(client, uri).await?;
+        Self::add_download(pool, uri, &download).await
     }
 
-    pub async fn add_and_update_feed(pool: &PgPool, uri: &str) -> Result<Feed> {
-        let raw_feed = get_feed(uri).await?;
-        let mut feed = Self::add_feed(pool, uri, &raw_feed).await?;
-        feed.update_feed_from_feed(pool, &raw_feed).await?;
+    /// Add the feed with its items, and the validators to fetch it again with.
+    async fn add_download(pool: &PgPool, uri: &str, download: &Download) -> Result<Feed> {
+        let mut feed = Self::add_feed(pool, uri, &download.feed).await?;
+        feed.update_feed_from_feed(pool, &download.feed).await?;
+        feed.set_validators(pool, download.etag.clone(), download.last_modified.clone()).await?;
         Ok(feed)
     }
 
-    pub async fn update_feed_from_feed(
+    /// Subscribe to `uri`, which may be a feed or a web page advertising one.
+    /// Subscribing to a feed already present returns the existing feed.
+    pub async fn subscribe(pool: &PgPool, client: &Client, uri: &str) -> Result<Subscription> {
+        let (uri, download) = match discover(client, uri).await? {
+            Discovery::Feed(uri, download) => (uri, *download),
+            Discovery::Candidates(candidates) if candidates.len() == 1 => {
+                let uri = candidates[0].uri.clone();
+                let download = get_feed(client, &uri).await?;
+                (uri, download)
+            }
+            Discovery::Candidates(candidates) => return Ok(Subscription::Choices(candidates)),
+        };
+        if let Ok(feed) = Self::get_feed_by_uri(pool, &uri).await {
+            return Ok(Subscription::Subscribed(Box::new(feed)));
+        }
+        let feed = Self::add_download(pool, &uri, &download).await?;
+        Ok(Subscription::Subscribed(Box::new(feed)))
+    }
+
+    pub async fn update_feed_from_feed(&mut self, pool: &PgPool, feed: &feed_rs::model::Feed) -> Result<UpdateSummary> {
+        let site = self.base_url();
+        let (summary, ()) = futures::join!(
+            self.store_entries(pool, feed),
+            Icon::refresh_if_stale(pool, self.id, &self.feed_uri, site.as_ref(), Some(feed)),
+        );
+        self.record_success(pool).await?;
+        Ok(summary)
+    }
+
+    /// Store the entries of `feed`, then extract the articles of new items.
+    async fn store_entries(&self, pool: &PgPool, feed: &feed_rs::model::Feed) -> UpdateSummary {
+        let mut summary = UpdateSummary::default();
+        let mut inserted = Vec::new();
+        for entry in feed.entries.iter() {
+            // One entry that cannot be stored does not hold back the others.
+            match self.create_item_from_entry(pool, entry).await {
+                Ok(Upsert::Inserted(id)) => {
+                    summary.inserted += 1;
+                    inserted.push(id);
+                }
+                Ok(Upsert::Updated) => summary.updated += 1,
+                Ok(Upsert::Unchanged) => summary.unchanged += 1,
+                Err(err) => log::warn!("Skipping entry {} of {}: {}", entry.id, self.feed_uri, err),
+            }
+        }
+        if self.full_text && !inserted.is_empty() {
+            self.extract_items(pool, &inserted).await;
+        }
+        summary
+    }
+
+    /// Record a successful fetch, which also lifts any `Retry-After` delay.
+    async fn record_success(&mut self, pool: &PgPool) -> Result<()> {
+        let now = offset::Utc::now();
+        query!(
+            "update feeds set last_fetched_at = $1, last_success_at = $1, next_fetch_at = least(next_fetch_at, $1), last_error = null, last_error_kind = null, consecutive_failures = 0 where id = $2",
+            now,
+            self.id
+        )
+        .execute(pool)
+        .await?;
+        self.last_fetched_at = Some(now);
+        self.last_success_at = Some(now);
+        self.next_fetch_at = self.next_fetch_at.min(now);
+        self.last_error = None;
+        self.last_error_kind = None;
+        self.consecutive_failures = 0;
+        Ok(())
+    }
+
+    async fn record_failure(&mut self, pool: &PgPool, err: &AppError) -> Result<()> {
+        let now = offset::Utc::now();
+        if let AppError::HttpError(_, Some(retry_after)) = err {
+            let retry_at = now + Duration::seconds(*retry_after as i64);
+            self.next_fetch_at = retry_at;
+        }
+        self.last_fetched_at = Some(now);
+        self.last_error = Some(err.to_string());
+        self.last_error_kind = Some(err.kind().to_string());
+        self.consecutive_failures += 1;
+        query!(
+            "update feeds set last_fetched_at = $1, last_error = $2, last_error_kind = $3, consecutive_failures = $4, next_fetch_at = $5 where id = $6",
+            now,
+            self.last_error,
+            self.last_error_kind,
+            self.consecutive_failures,
+            self.next_fetch_at,
+            self.id
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Fetch the feed again, recording whether it failed.
+    pub async fn update_feed(&mut self, pool: &PgPool, client: &Client) -> Result<UpdateSummary> {
+        let result = self.fetch_and_update(pool, client).await;
+        if let Err(err) = &result {
+            self.record_failure(pool, err).await?;
+        }
+        result
+    }
+
+    /// Fetch the feed again, skipping it if the server reports it unchanged.
+    async fn fetch_and_update(&mut self, pool: &PgPool, client: &Client) -> Result<UpdateSummary> {
+        let fetched = fetch_feed(
+            client,
+            &self.feed_uri,
+            self.etag.as_deref(),
+            self.last_modified.as_deref(),
+        )
+        .await?;
+        let download = match fetched {
+            FetchResult::NotModified => {
+                let site = self.base_url();
+                Icon::refresh_if_stale(pool, self.id, &self.feed_uri, site.as_ref(), None).await;
+                self.record_success(pool).await?;
+                return Ok(UpdateSummary::default());
+            }
+            FetchResult::Modified(download) => *download,
+        };
+        let summary = self.update_feed_from_feed(pool, &download.feed).await?;
+        self.set_validators(pool, download.etag, download.last_modified).await?;
+        Ok(summary)
+    }
+
+    async fn set_validators(
         &mut self,
         pool: &PgPool,
-        feed: &feed_rs::model::Feed,
+        etag: Option<String>,
+        last_modified: Option<String>,
     ) -> Result<()> {
-        for entry in feed.entries.iter() {
-            self.create_item_from_entry(pool, entry).await.unwrap();
-        }
+        query!(
+            "update feeds set etag = $1, last_modified = $2 where id = $3",
+            etag,
+            last_modified,
+            self.id
+        )
+        .execute(pool)
+        .await?;
+        self.etag = etag;
+        self.last_modified = last_modified;
         Ok(())
     }
+}
+
+/// Fetch `uri` with `If-None-Match`/`If-Modified-Since` taken from the previous response.
+pub async fn fetch_feed(
+    client: &Client,
+    uri: &str,
+    etag: Option<&str>,
+    last_modified: Option<&str>,
+) -> Result<FetchResult> {
+    let mut request = client.get(uri);
+    if let Some(etag) = etag {
+        request = request.header(header::IF_NONE_MATCH, etag);
+    }
+    if let Some(last_modified) = last_modified {
+        request = request.header(header::IF_MODIFIED_SINCE, last_modified);
+    }
+    let response = request.send().await?;
+    let status = response.status();
+    if status == StatusCode::NOT_MODIFIED {
+        return Ok(FetchResult::NotModified);
+    }
+    let response = check_status(response)?;
+    let headers = response.headers().clone();
+    let body = read_body(response, MAX_BODY_SIZE).await?;
+    Ok(FetchResult::Modified(Box::new(Download::parse(&headers, &body)?)))
+}
+
+/// The link to the web page of a feed or entry, rather than to itself or its media.
+fn alternate(links: &[feed_rs::model::Link]) -> Option<&feed_rs::model::Link> {
+    links
+        .iter()
+        .find(|l| matches!(l.rel.as_deref(), None | Some("alternate")))
+        .or(links.first())
+}
+
+/// The xml:base of inline content. feed-rs only reports it for RSS
+/// `content:encoded`, as the content's `src`; a `src` without a body would be
+/// where out-of-line content lives rather than a base.
+fn xml_base(entry: &feed_rs::model::Entry) -> Option<Url> {
+    let content = entry.content.as_ref().filter(|c| c.body.is_some())?;
+    Url::parse(&content.src.as_ref()?.href).ok()
+}
+
+/// The guid of the entry, unless feed-rs made one up because the feed has
+/// none: a hash of its first link and title, or else a random UUID.
+fn guid(entry: &feed_rs::model::Entry) -> Option<String> {
+    let generated = match entry.links.first() {
+        Some(link) => {
+            // Same keys and input as feed-rs' `create_id`.
+            let mut hasher = SipHasher::new_with_keys(0x5d78_4074_2887_2d60, 0x90ee_ca4c_90a5_e228);
+            hasher.write(link.href.as_bytes());
+            if let Some(title) = &entry.title {
+                hasher.write(title.content.as_bytes());
+            }
+            let hash = hasher.finish128();
+            entry.id == format!("{:x}{:x}", hash.h1, hash.h2)
+        }
+        None => is_uuid_v4(&entry.id),
+    };
+    Some(entry.id.clone()).filter(|id| !id.is_empty() && !generated)
+}
 
-    pub async fn update_feed(&mut self, pool: &PgPool) -> Result<()> {
-        let f = get_feed(&self.feed_uri).await?;
-        self.update_feed_from_feed(pool, &f).await
+/// Whether `id` is formatted like the random UUIDs feed-rs generates.
+fn is_uuid_v4(id: &str) -> bool {
+    id.len() == 36
+        && id.char_indices().all(|(i, c)| match i {
+            8 | 13 | 18 | 23 => c == '-',
+            14 => c == '4',
+            _ => matches!(c, '0'..='9' | 'a'..='f'),
+        })
+}
+
+/// A picture for the entry: a media thumbnail, or else an image among its media.
+fn thumbnail(entry: &feed_rs::model::Entry) -> Option<String> {
+    let thumbnail = entry
+        .media
+        .iter()
+        .flat_map(|m| m.thumbnails.iter())
+        .map(|t| t.image.uri.clone())
+        .next();
+    thumbnail.or_else(|| {
+        entry
+            .media
+            .iter()
+            .flat_map(|m| m.content.iter())
+            .find(|c| c.content_type.as_ref().map(|t| t.type_() == "image").unwrap_or(false))
+            .and_then(|c| c.url.as_ref().map(|u| u.to_string()))
+    })
+}
+
+/// Media files of the entry: media contents (RSS enclosures among them) and atom enclosure links.
+fn enclosures(entry: &feed_rs::model::Entry, base: Option<&Url>) -> Vec<NewEnclosure> {
+    let mut enclosures: Vec<NewEnclosure> = Vec::new();
+    for media in entry.media.iter() {
+        let image = media.thumbnails.first().and_then(|t| http_url(base, &t.image.uri));
+        for content in media.content.iter() {
+            let url = match content.url.as_ref().and_then(|url| http_url(base, url.as_str())) {
+                Some(url) => url,
+                None => continue,
+            };
+            enclosures.push(NewEnclosure {
+                url,
+                mime_type: content.content_type.as_ref().map(|t| t.to_string()),
+                length: content.size.map(|s| s as i64),
+                duration: content.duration.or(media.duration).map(|d| d.as_secs() as i64),
+                image: image.clone(),
+            });
+        }
     }
+    for link in entry.links.iter().filter(|l| l.rel.as_deref() == Some("enclosure")) {
+        let url = match http_url(base, &link.href) {
+            Some(url) => url,
+            None => continue,
+        };
+        enclosures.push(NewEnclosure {
+            url,
+            mime_type: link.media_type.clone(),
+            length: link.length.map(|l| l as i64),
+            duration: None,
+            image: None,
+        });
+    }
+    let mut seen = Vec::new();
+    enclosures.retain(|e| {
+        let first = !seen.contains(&e.url);
+        seen.push(e.url.clone());
+        first
+    });
+    enclosures
+}
+
+pub async fn get_feed(client: &Client, uri: &str) -> Result<Download> {
+    let response = check_status(client.get(uri).send().await?)?;
+    let headers = response.headers().clone();
+    let body = read_body(response, MAX_BODY_SIZE).await?;
+    Download::parse(&headers, &body)
 }
 
-pub async fn get_feed(uri: &str) -> Result<feed_rs::model::Feed> {
-    let src = reqwest::get(uri).await?.text().await?;
-    Ok(feed_rs::parser::parse(src.as_bytes())?)
+#[cfg(test)]
+mod tests {
+    use super::{guid, xml_base};
+    use url::Url;
+
+    fn entries(xml: &str) -> Vec<feed_rs::model::Entry> {
+        feed_rs::parser::parse(xml.as_bytes()).unwrap().entries
+    }
+
+    fn rss(item: &str) -> String {
+        format!("<rss version=\"2.0\"><channel><title>T</title>{}</channel></rss>", item)
+    }
+
+    #[test]
+    fn keeps_guids_of_the_feed() {
+        let before = entries(&rss("<item><guid>post-1</guid><title>Old</title><link>http://a/1</link></item>"));
+        let after = entries(&rss("<item><guid>post-1</guid><title>New</title><link>http://a/1</link></item>"));
+        assert_eq!(guid(&before[0]).as_deref(), Some("post-1"));
+        assert_eq!(guid(&after[0]).as_deref(), Some("post-1"));
+        let atom = entries(
+            r#"<feed xmlns="http://www.w3.org/2005/Atom"><title>T</title>
+                <entry><id>tag:a,2024:1</id><title>A</title></entry></feed>"#,
+        );
+        assert_eq!(guid(&atom[0]).as_deref(), Some("tag:a,2024:1"));
+    }
+
+    #[test]
+    fn drops_ids_hashed_from_link_and_title() {
+        let before = entries(&rss("<item><title>Old</title><link>http://a/1</link></item>"));
+        let after = entries(&rss("<item><title>New</title><link>http://a/1</link></item>"));
+        assert!(!before[0].id.is_empty());
+        assert_ne!(before[0].id, after[0].id);
+        assert_eq!(guid(&before[0]), None);
+        assert_eq!(guid(&after[0]), None);
+    }
+
+    #[test]
+    fn drops_random_ids_without_link() {
+        let items = entries(&rss("<item><title>A</title><description>Text</description></item>"));
+        assert!(!items[0].id.is_empty());
+        assert_eq!(guid(&items[0]), None);
+    }
+
+    #[test]
+    fn takes_the_xml_base_of_inline_content() {
+        let feed = |base: &str| {
+            rss(&format!(
+                r#"<item xmlns:content="http://purl.org/rss/1.0/modules/content/"><guid>1</guid>
+                    <content:encoded{}><![CDATA[<img src="a.png">]]></content:encoded></item>"#,
+                base
+            ))
+        };
+        let with_base = entries(&feed(r#" xml:base="http://cdn.example.com/img/""#));
+        assert_eq!(
+            xml_base(&with_base[0]),
+            Url::parse("http://cdn.example.com/img/").ok()
+        );
+        assert_eq!(xml_base(&entries(&feed(""))[0]), None);
+        let no_content = entries(&rss("<item><guid>1</guid><description>Hi</description></item>"));
+        assert_eq!(xml_base(&no_content[0]), None);
+    }
 }
@@ -0,0 +1,203 @@
+use crate::error::Result;
+use crate::http::{check_status, public_client, read_body, MAX_BODY_SIZE};
+use crate::sanitize::resolve;
+use chrono::{DateTime, Duration, Utc};
+use reqwest::Client;
+use scraper::{Html, Selector};
+use sqlx::*;
+use url::Url;
+
+/// Days before the icon of a feed is looked up again.
+const ICON_REFRESH_DAYS: i64 = 7;
+/// Larger images are not stored as icons.
+const MAX_ICON_SIZE: usize = 512 * 1024;
+
+/// The icon of a feed, as served from `/icons/{feed_id}`.
+#[derive(FromRow)]
+pub struct Icon {
+    pub feed_id: i64,
+    pub mime_type: String,
+    pub data: Vec<u8>,
+    pub fetched_at: DateTime<Utc>,
+}
+
+impl Icon {
+    pub async fn get_icon(pool: &PgPool, feed_id: i64) -> Result<Option<Icon>> {
+        let icon = query_as!(
+            Icon,
+            r#"select feed_id, mime_type as "mime_type!", data as "data!", fetched_at from icons where feed_id = $1 and data is not null"#,
+            feed_id
+        )
+        .fetch_optional(pool)
+        .await?;
+        Ok(icon)
+    }
+
+    /// Ids of the feeds that have an icon.
+    pub async fn feed_ids(pool: &PgPool) -> Result<Vec<i64>> {
+        let ids = query!("select feed_id from icons where data is not null")
+            .fetch_all(pool)
+            .await?
+            .into_iter()
+            .map(|r| r.feed_id)
+            .collect();
+        Ok(ids)
+    }
+
+    /// Whether the icon of the feed was never looked up or is due for a refresh.
+    pub async fn is_stale(pool: &PgPool, feed_id: i64) -> Result<bool> {
+        let fetched_at = query!("select fetched_at from icons where feed_id = $1", feed_id)
+            .fetch_optional(pool)
+            .await?
+            .map(|r| r.fetched_at);
+        Ok(fetched_at
+            .map(|t| t + Duration::days(ICON_REFRESH_DAYS) <= Utc::now())
+            .unwrap_or(true))
+    }
+
+    /// Refresh the icon of a feed if it is due. Failures are only logged, as
+    /// a feed without a reachable icon still updates fine.
+    pub async fn refresh_if_stale(
+        pool: &PgPool,
+        feed_id: i64,
+        feed_uri: &str,
+        site: Option<&Url>,
+        feed: Option<&feed_rs::model::Feed>,
+    ) {
+        let refreshed = match Icon::is_stale(pool, feed_id).await {
+            Ok(true) => Icon::refresh(pool, feed_id, feed_uri, site, feed).await,
+            Ok(false) => Ok(()),
+            Err(err) => Err(err),
+        };
+        if let Err(err) = refreshed {
+            log::warn!("Failed to refresh the icon of {}: {}", feed_uri, err);
+        }
+    }
+
+    /// Look up the icon of a feed and store it, or store that there is none so
+    /// that the lookup waits for the next refresh. Without the parsed `feed`,
+    /// the icon found last time is tried before those of the site. Every
+    /// candidate comes from the feed, so only public addresses are fetched.
+    pub async fn refresh(
+        pool: &PgPool,
+        feed_id: i64,
+        feed_uri: &str,
+        site: Option<&Url>,
+        feed: Option<&feed_rs::model::Feed>,
+    ) -> Result<()> {
+        let client = public_client();
+        let mut candidates = match feed {
+            Some(feed) => {
+                let feed_base = Url::parse(feed_uri).ok();
+                feed.icon
+                    .iter()
+                    .chain(feed.logo.iter())
+                    .map(|image| resolve(feed_base.as_ref(), &image.uri))
+                    .collect::<Vec<String>>()
+            }
+            None => query!("select source_uri from icons where feed_id = $1", feed_id)
+                .fetch_optional(pool)
+                .await?
+                .and_then(|r| r.source_uri)
+                .into_iter()
+                .collect(),
+        };
+        if let Some(site) = site {
+            candidates.extend(page_icons(&client, site).await);
+            candidates.extend(site.join("/favicon.ico").map(|u| u.to_string()));
+        }
+        let mut icon = None;
+        for uri in candidates {
+            if let Some((mime_type, data)) = download(&client, &uri).await {
+                icon = Some((mime_type, data, uri));
+                break;
+            }
+        }
+        let (mime_type, data, source_uri) = match icon {
+            Some((mime_type, data, uri)) => (Some(mime_type), Some(data), Some(uri)),
+            None => (None, None, None),
+        };
+        query!(
+            "insert into icons (feed_id, mime_type, data, source_uri, fetched_at) values ($1, $2, $3, $4, now())
+            on conflict (feed_id) do update set mime_type = excluded.mime_type, data = excluded.data, source_uri = excluded.source_uri, fetched_at = excluded.fetched_at",
+            feed_id,
+            mime_type,
+            data,
+            source_uri
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+}
+
+/// Icons advertised by `<link rel="icon">` tags of the site's home page.
+async fn page_icons(client: &Client, site: &Url) -> Vec<String> {
+    let html = match get(client, site.as_str(), MAX_BODY_SIZE).await {
+        Ok(html) => String::from_utf8_lossy(&html).into_owned(),
+        Err(_) => return Vec::new(),
+    };
+    let document = Html::parse_document(&html);
+    let selector = Selector::parse("link[rel~=icon][href]").unwrap();
+    document
+        .select(&selector)
+        .filter_map(|link| link.value().attr("href"))
+        .map(|href| resolve(Some(site), href))
+        .collect()
+}
+
+/// Content type and body of `uri`, if it is a small enough image.
+async fn download(client: &Client, uri: &str) -> Option<(String, Vec<u8>)> {
+    let response = check_status(client.get(uri).send().await.ok()?).ok()?;
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|t| t.to_str().ok())
+        .map(|t| t.to_ascii_lowercase());
+    let data = read_body(response, MAX_ICON_SIZE).await.ok()?;
+    Some((image_type(content_type, &data)?, data))
+}
+
+/// Body of `uri`, up to `max` bytes.
+async fn get(client: &Client, uri: &str, max: usize) -> Result<Vec<u8>> {
+    let response = check_status(client.get(uri).send().await?)?;
+    read_body(response, max).await
+}
+
+/// The type of an icon: the declared image type, or else one recognised by
+/// its header, as servers often label favicon.ico as a generic binary.
+fn image_type(content_type: Option<String>, data: &[u8]) -> Option<String> {
+    if data.is_empty() {
+        return None;
+    }
+    match content_type {
+        Some(t) if t.starts_with("image/") => Some(t),
+        _ if data.starts_with(b"\x00\x00\x01\x00") => Some("image/x-icon".to_string()),
+        _ if data.starts_with(b"\x89PNG") => Some("image/png".to_string()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::image_type;
+
+    #[test]
+    fn trusts_declared_image_types() {
+        let svg = Some("image/svg+xml".to_string());
+        assert_eq!(image_type(svg, b"<svg/>"), Some("image/svg+xml".to_string()));
+    }
+
+    #[test]
+    fn recognises_icons_by_header() {
+        let binary = || Some("application/octet-stream".to_string());
+        assert_eq!(image_type(binary(), b"\x00\x00\x01\x00\x01"), Some("image/x-icon".to_string()));
+        assert_eq!(image_type(None, b"\x89PNG\r\n"), Some("image/png".to_string()));
+    }
+
+    #[test]
+    fn rejects_other_bodies() {
+        assert_eq!(image_type(Some("text/html".to_string()), b"<html>"), None);
+        assert_eq!(image_type(Some("image/png".to_string()), b""), None);
+    }
+}
@@ -1,24 +1,63 @@
 use base64::{engine::general_purpose::STANDARD, Engine};
-use feed_rs::model::Entry;
 use sqlx::*;
+use std::collections::HashMap;
+use url::Url;
 use chrono::{DateTime, Utc};
+use crate::model::enclosure::{Enclosure, NewEnclosure};
 use crate::model::feed::Feed;
+use crate::model::stream::{Stream, UnreadCount};
 use crate::error::Result;
+use crate::extract;
+use crate::http::public_client;
+use crate::sanitize::{escape, http_url, sanitize};
 use md5::{Md5, Digest};
 
 #[derive(sqlx::FromRow)]
 pub struct Item {
     pub id: i64,
     pub feed_id: i64,
+    pub guid: Option<String>,
     pub hash: String,
     pub link: Option<String>,
     pub title: String,
     pub author: String,
     pub content: String,
+    /// Article extracted from `link`, served instead of `content` when present.
+    pub extracted_content: Option<String>,
+    pub summary: Option<String>,
+    /// MIME type of `content`.
+    pub content_type: Option<String>,
+    pub categories: Vec<String>,
+    pub thumbnail: Option<String>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    pub crawled_at: DateTime<Utc>,
     pub read: bool,
-    pub star: bool
+    pub star: bool,
+    /// Seconds into the enclosure where playback was left.
+    pub playback_position: Option<f64>
+}
+
+/// An entry as read from a feed, before it is stored.
+pub struct NewItem {
+    pub guid: Option<String>,
+    pub link: Option<String>,
+    pub title: String,
+    pub author: String,
+    pub content: String,
+    pub summary: Option<String>,
+    pub content_type: Option<String>,
+    pub categories: Vec<String>,
+    pub thumbnail: Option<String>,
+    pub enclosures: Vec<NewEnclosure>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+pub enum Upsert {
+    Inserted(i64),
+    Updated,
+    Unchanged,
 }
 
 impl Item {
@@ -26,14 +65,44 @@ impl Item {
         Feed::get_feed_by_id(pool, self.feed_id).await
     }
 
-    pub async fn create(pool: &PgPool, feed_id: i64, link: Option<&str>, title: &str, author: &str, content: &str, created_at: DateTime<Utc>, updated_at: DateTime<Utc>) -> Result<Item> {
+    /// Store `new` as an item of the feed, or update the item it matches: by guid, or
+    /// when either has none by hash, then by link and title together, as some feeds
+    /// point every entry at the same page. Read and starred states are kept.
+    pub async fn upsert(pool: &PgPool, feed_id: i64, new: &NewItem) -> Result<Upsert> {
         let mut hasher = Md5::new();
-        hasher.update(title.as_bytes());
-        hasher.update(content.as_bytes());
-        let hash = hasher.finalize();
-        let hash_text = STANDARD.encode(hash);
-        let id = query!("insert into items (feed_id, hash, title, author, content, created_at, updated_at) values ($1, $2, $3, $4, $5, $6, $7) returning id", feed_id, hash_text.as_str(), title, author, content, created_at, updated_at).fetch_one(pool).await?.id;
-        Ok(Item {id, feed_id, hash: hash_text, link: link.map(|x| x.to_string()), title: title.to_string(), author: author.to_string(), content: content.to_string(), created_at, updated_at, star: false, read: false })
+        hasher.update(new.title.as_bytes());
+        hasher.update(new.content.as_bytes());
+        let hash = STANDARD.encode(hasher.finalize());
+        let existing = query!("select id, guid, hash, link, summary, content_type, categories, thumbnail from items where feed_id = $1 and (guid = $2 or ((guid is null or $2::text is null) and (hash = $4 or (link = $3 and title = $5))))
+            order by guid = $2 desc nulls last, hash = $4 desc limit 1", feed_id, new.guid, new.link, hash, new.title).fetch_optional(pool).await?;
+        let existing = match existing {
+            Some(existing) => existing,
+            None => {
+                let inserted = query!("insert into items (feed_id, guid, hash, link, title, author, content, summary, content_type, categories, thumbnail, created_at, updated_at) values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13) on conflict (feed_id, guid) do nothing returning id",
+                    feed_id, new.guid, hash, new.link, new.title, new.author, new.content, new.summary, new.content_type, &new.categories, new.thumbnail, new.created_at, new.updated_at).fetch_optional(pool).await?;
+                return match inserted {
+                    Some(inserted) => {
+                        Enclosure::sync(pool, inserted.id, &new.enclosures).await?;
+                        Ok(Upsert::Inserted(inserted.id))
+                    }
+                    None => Ok(Upsert::Unchanged),
+                };
+            }
+        };
+        let enclosures_changed = Enclosure::sync(pool, existing.id, &new.enclosures).await?;
+        let unchanged = existing.hash == hash
+            && existing.guid == new.guid
+            && existing.link == new.link
+            && existing.summary == new.summary
+            && existing.content_type == new.content_type
+            && existing.categories == new.categories
+            && existing.thumbnail == new.thumbnail;
+        if unchanged {
+            return Ok(if enclosures_changed { Upsert::Updated } else { Upsert::Unchanged });
+        }
+        query!("update items set guid = $1, hash = $2, link = $3, title = $4, author = $5, content = $6, summary = $7, content_type = $8, categories = $9, thumbnail = $10, updated_at = $11 where id = $12",
+            new.guid, hash, new.link, new.title, new.author, new.content, new.summary, new.content_type, &new.categories, new.thumbnail, new.updated_at, existing.id).execute(pool).await?;
+        Ok(Upsert::Updated)
     }
 
     pub async fn save(&self, pool: &PgPool) -> Result<()> {
@@ -41,13 +110,157 @@ impl Item {
         Ok(())
     }
 
+    /// The content to show: the extracted article if there is one.
+    pub fn body(&self) -> &str {
+        self.extracted_content.as_deref().unwrap_or(&self.content)
+    }
+
+    /// Whether `body` is plain text rather than (sanitized) HTML.
+    pub fn is_plain_text(&self) -> bool {
+        self.extracted_content.is_none()
+            && self
+                .content_type
+                .as_deref()
+                .map(|t| t.starts_with("text/plain"))
+                .unwrap_or(false)
+    }
+
+    /// `body` as HTML.
+    pub fn body_html(&self) -> String {
+        match self.is_plain_text() {
+            true => escape(self.body()),
+            false => self.body().to_string(),
+        }
+    }
+
+    /// Download the item link, which comes from the feed and so only through
+    /// the public client, and store the article extracted from it.
+    /// Returns whether an article was found.
+    pub async fn extract_content(&mut self, pool: &PgPool) -> Result<bool> {
+        let link = match &self.link {
+            Some(link) => link,
+            None => return Ok(false),
+        };
+        let extracted = match extract::extract(&public_client(), link).await? {
+            Some(extracted) => extracted,
+            None => return Ok(false),
+        };
+        let extracted = sanitize(&extracted, Url::parse(link).ok().as_ref());
+        query!("update items set extracted_content = $1 where id = $2", extracted, self.id).execute(pool).await?;
+        self.extracted_content = Some(extracted);
+        Ok(true)
+    }
+
+    /// Clean the HTML of every item again and drop links and enclosures that
+    /// are not http(s), for items stored by earlier versions. Returns how many
+    /// items changed.
+    pub async fn sanitize_all(pool: &PgPool) -> Result<u64> {
+        let mut changed = 0;
+        let mut last_id = 0;
+        loop {
+            let rows = query!("select id, link, content, summary, extracted_content, content_type, thumbnail from items where id > $1 order by id limit 500", last_id).fetch_all(pool).await?;
+            let Some(last) = rows.last() else { break };
+            last_id = last.id;
+            for row in rows {
+                let link = row.link.as_deref().and_then(|l| http_url(None, l));
+                let base = link.as_deref().and_then(|l| Url::parse(l).ok());
+                let content = match &row.content_type {
+                    Some(t) if t.starts_with("text/plain") => row.content.clone(),
+                    _ => sanitize(&row.content, base.as_ref()),
+                };
+                let summary = row.summary.as_deref().map(|s| sanitize(s, base.as_ref()));
+                let extracted_content = row.extracted_content.as_deref().map(|s| sanitize(s, base.as_ref()));
+                let thumbnail = row.thumbnail.as_deref().and_then(|t| http_url(None, t));
+                let unchanged = link == row.link
+                    && content == row.content
+                    && summary == row.summary
+                    && extracted_content == row.extracted_content
+                    && thumbnail == row.thumbnail;
+                if unchanged {
+                    continue;
+                }
+                query!("update items set link = $1, content = $2, summary = $3, extracted_content = $4, thumbnail = $5 where id = $6",
+                    link, content, summary, extracted_content, thumbnail, row.id).execute(pool).await?;
+                changed += 1;
+            }
+        }
+        query!("delete from enclosures where url !~* '^https?://'").execute(pool).await?;
+        query!("update enclosures set image = null where image !~* '^https?://'").execute(pool).await?;
+        Ok(changed)
+    }
+
+    pub async fn set_playback_position(&mut self, pool: &PgPool, position: f64) -> Result<()> {
+        query!("update items set playback_position = $1 where id = $2", position, self.id).execute(pool).await?;
+        self.playback_position = Some(position);
+        Ok(())
+    }
+
+    pub async fn enclosures(&self, pool: &PgPool) -> Result<Vec<Enclosure>> {
+        let mut enclosures = Enclosure::enclosures(pool, &[self.id]).await?;
+        Ok(enclosures.remove(&self.id).unwrap_or_default())
+    }
+
     pub async fn unread_count(pool: &PgPool) -> Result<i64> {
         let count = query!("select count(id) as unread_count from items where read = false").fetch_one(pool).await.map(|row| row.unread_count.unwrap_or(0))?;
         Ok(count)
     }
 
+    /// Unread count of the whole reading list.
+    pub async fn unread_summary(pool: &PgPool) -> Result<UnreadCount> {
+        let row = query!("select count(id) filter (where read = false) as \"count!\", max(crawled_at) as newest from items").fetch_one(pool).await?;
+        Ok(UnreadCount { count: row.count, newest: row.newest })
+    }
+
+    /// Mark read every item that arrived before `before`.
+    pub async fn mark_all_read(pool: &PgPool, before: DateTime<Utc>) -> Result<()> {
+        query!("update items set read = true where read = false and crawled_at <= $1", before).execute(pool).await?;
+        Ok(())
+    }
+
     pub async fn get_item_by_id(pool: &PgPool, id: i64) -> Result<Item> {
-        let item = query_as!(Item, "select id, feed_id, hash, link, title, author, content, created_at, updated_at, read, star from items where id = $1", id).fetch_one(pool).await?;
+        let item = query_as!(Item, "select id, feed_id, guid, hash, link, title, author, content, extracted_content, summary, content_type, categories, thumbnail, created_at, updated_at, crawled_at, read, star, playback_position from items where id = $1", id).fetch_one(pool).await?;
         Ok(item)
     }
+
+    pub async fn get_items_by_ids(pool: &PgPool, ids: &[i64]) -> Result<Vec<Item>> {
+        let items = query_as!(Item, "select id, feed_id, guid, hash, link, title, author, content, extracted_content, summary, content_type, categories, thumbnail, created_at, updated_at, crawled_at, read, star, playback_position from items where id = any($1)", ids).fetch_all(pool).await?;
+        Ok(items)
+    }
+
+    /// Label names of each item, keyed by item id.
+    pub async fn labels(pool: &PgPool, ids: &[i64]) -> Result<HashMap<i64, Vec<String>>> {
+        let rows = query!("select items.id as \"id!\", tags.name as \"name!\" from items join taggings on taggings.feed_id = items.feed_id join tags on tags.id = taggings.tag_id where items.id = any($1)
+            union select item_taggings.item_id, tags.name from item_taggings join tags on tags.id = item_taggings.tag_id where item_taggings.item_id = any($1)", ids).fetch_all(pool).await?;
+        let mut labels: HashMap<i64, Vec<String>> = HashMap::new();
+        for row in rows {
+            labels.entry(row.id).or_default().push(row.name);
+        }
+        Ok(labels)
+    }
+
+    /// Add and remove the read, starred and label states of several items in one transaction.
+    /// Other streams cannot be attached to an item and are ignored.
+    pub async fn edit_tags(pool: &PgPool, ids: &[i64], add: &[Stream], remove: &[Stream]) -> Result<()> {
+        let mut tx = pool.begin().await?;
+        for (stream, set) in add.iter().map(|s| (s, true)).chain(remove.iter().map(|s| (s, false))) {
+            match stream {
+                Stream::Read => {
+                    query!("update items set read = $1 where id = any($2)", set, ids).execute(&mut *tx).await?;
+                }
+                Stream::Starred => {
+                    query!("update items set star = $1 where id = any($2)", set, ids).execute(&mut *tx).await?;
+                }
+                Stream::Label(name) if set => {
+                    query!("insert into tags (name) values ($1) on conflict (name) do nothing", name).execute(&mut *tx).await?;
+                    query!("insert into item_taggings (item_id, tag_id) select item_id, tags.id from unnest($1::bigint[]) as item_id, tags where tags.name = $2 on conflict do nothing", ids, name).execute(&mut *tx).await?;
+                }
+                Stream::Label(name) => {
+                    query!("delete from item_taggings using tags where tags.id = item_taggings.tag_id and tags.name = $1 and item_taggings.item_id = any($2)", name, ids).execute(&mut *tx).await?;
+                }
+                _ => {}
+            }
+        }
+        tx.commit().await?;
+        Ok(())
+    }
 }
\ No newline at end of file
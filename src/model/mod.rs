@@ -1,3 +1,8 @@
+pub mod enclosure;
 pub mod feed;
+pub mod icon;
 pub mod item;
+pub mod preference;
+pub mod session;
+pub mod stream;
 pub mod tag;
\ No newline at end of file
@@ -0,0 +1,43 @@
+use crate::error::Result;
+use crate::model::session::Session;
+use sqlx::*;
+
+/// A per-stream preference of an account, such as `subscription-ordering`.
+#[derive(FromRow)]
+pub struct StreamPreference {
+    pub stream_id: String,
+    pub key: String,
+    pub value: String,
+}
+
+impl StreamPreference {
+    pub async fn preferences(pool: &PgPool, session: &Session) -> Result<Vec<StreamPreference>> {
+        let preferences = query_as!(
+            StreamPreference,
+            "select stream_id, key, value from stream_prefs where session_id = $1 order by stream_id, key",
+            session.id
+        )
+        .fetch_all(pool)
+        .await?;
+        Ok(preferences)
+    }
+
+    pub async fn set(
+        pool: &PgPool,
+        session: &Session,
+        stream_id: &str,
+        key: &str,
+        value: &str,
+    ) -> Result<()> {
+        query!(
+            "insert into stream_prefs (session_id, stream_id, key, value) values ($1, $2, $3, $4) on conflict (session_id, stream_id, key) do update set value = excluded.value",
+            session.id,
+            stream_id,
+            key,
+            value
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,160 @@
+use crate::error::Result;
+use argon2::password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use chrono::{offset, Duration};
+use rand::{distributions::Alphanumeric, Rng};
+use sqlx::*;
+
+/// Lifetime of a token issued by ClientLogin.
+pub const AUTH_TOKEN_LIFETIME_DAYS: i64 = 30;
+/// Lifetime of an edit token handed out by `/api/0/token`.
+const EDIT_TOKEN_LIFETIME_MINUTES: i64 = 30;
+
+#[derive(FromRow)]
+pub struct Session {
+    pub id: i64,
+    pub email: String,
+    pub password: String,
+}
+
+impl Session {
+    /// Create the account or replace its password, revoking every token issued so far.
+    pub async fn create(pool: &PgPool, email: &str, password: &str) -> Result<Session> {
+        let salt = SaltString::generate(&mut OsRng);
+        let hash = Argon2::default()
+            .hash_password(password.as_bytes(), &salt)?
+            .to_string();
+        let id = query!(
+            "insert into session (email, password) values ($1, $2) on conflict (email) do update set password = excluded.password returning id",
+            email,
+            hash
+        )
+        .fetch_one(pool)
+        .await?
+        .id;
+        let session = Session {
+            id,
+            email: email.to_string(),
+            password: hash,
+        };
+        session.revoke_tokens(pool).await?;
+        Ok(session)
+    }
+
+    pub async fn get_session_by_email(pool: &PgPool, email: &str) -> Result<Option<Session>> {
+        let session = query_as!(
+            Session,
+            "select id, email, password from session where email = $1",
+            email
+        )
+        .fetch_optional(pool)
+        .await?;
+        Ok(session)
+    }
+
+    /// Session owning `token`, if the token is neither revoked nor expired.
+    pub async fn get_session_by_token(pool: &PgPool, token: &str) -> Result<Option<Session>> {
+        let session = query_as!(
+            Session,
+            "select session.id, session.email, session.password from auth_tokens join session on session.id = auth_tokens.session_id where token = $1 and revoked = false and expires_at > now()",
+            token
+        )
+        .fetch_optional(pool)
+        .await?;
+        Ok(session)
+    }
+
+    /// Check the credentials and issue a new auth token on success.
+    pub async fn login(pool: &PgPool, email: &str, password: &str) -> Result<Option<String>> {
+        let session = match Self::get_session_by_email(pool, email).await? {
+            Some(session) => session,
+            None => return Ok(None),
+        };
+        if !session.verify_password(password) {
+            return Ok(None);
+        }
+        session.issue_token(pool).await.map(Some)
+    }
+
+    pub fn verify_password(&self, password: &str) -> bool {
+        PasswordHash::new(&self.password)
+            .map(|hash| {
+                Argon2::default()
+                    .verify_password(password.as_bytes(), &hash)
+                    .is_ok()
+            })
+            .unwrap_or(false)
+    }
+
+    pub async fn issue_token(&self, pool: &PgPool) -> Result<String> {
+        let token = random_token();
+        let now = offset::Utc::now();
+        let expires_at = now + Duration::days(AUTH_TOKEN_LIFETIME_DAYS);
+        query!(
+            "insert into auth_tokens (token, session_id, created_at, expires_at) values ($1, $2, $3, $4)",
+            token,
+            self.id,
+            now,
+            expires_at
+        )
+        .execute(pool)
+        .await?;
+        Ok(token)
+    }
+
+    /// Issue a short-lived edit token bound to `auth_token`, dropping the ones that expired.
+    pub async fn issue_edit_token(pool: &PgPool, auth_token: &str) -> Result<String> {
+        query!("delete from edit_tokens where expires_at <= now()")
+            .execute(pool)
+            .await?;
+        let token = random_token();
+        let expires_at = offset::Utc::now() + Duration::minutes(EDIT_TOKEN_LIFETIME_MINUTES);
+        query!(
+            "insert into edit_tokens (token, auth_token, expires_at) values ($1, $2, $3)",
+            token,
+            auth_token,
+            expires_at
+        )
+        .execute(pool)
+        .await?;
+        Ok(token)
+    }
+
+    pub async fn check_edit_token(pool: &PgPool, auth_token: &str, edit_token: &str) -> Result<bool> {
+        let valid = query!(
+            "select exists(select 1 from edit_tokens join auth_tokens on auth_tokens.token = edit_tokens.auth_token where edit_tokens.token = $1 and edit_tokens.auth_token = $2 and edit_tokens.expires_at > now() and auth_tokens.revoked = false) as valid",
+            edit_token,
+            auth_token
+        )
+        .fetch_one(pool)
+        .await?
+        .valid
+        .unwrap_or(false);
+        Ok(valid)
+    }
+
+    pub async fn revoke_token(pool: &PgPool, token: &str) -> Result<()> {
+        query!("update auth_tokens set revoked = true where token = $1", token)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn revoke_tokens(&self, pool: &PgPool) -> Result<()> {
+        query!(
+            "update auth_tokens set revoked = true where session_id = $1",
+            self.id
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+}
+
+pub fn random_token() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(64)
+        .map(char::from)
+        .collect()
+}
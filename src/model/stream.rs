@@ -0,0 +1,120 @@
+use crate::error::Result;
+use chrono::{DateTime, Utc};
+use sqlx::*;
+
+/// A set of items addressable as one stream.
+pub enum Stream {
+    Feed(i64),
+    Label(String),
+    ReadingList,
+    Starred,
+    Read,
+}
+
+/// Which items of a stream to return and in what order.
+pub struct StreamFilter {
+    pub stream: Stream,
+    pub count: i64,
+    pub oldest_first: bool,
+    /// Exclude items that arrived before this time.
+    pub start_time: Option<DateTime<Utc>>,
+    /// Exclude items that arrived after this time.
+    pub end_time: Option<DateTime<Utc>>,
+    pub read: Option<bool>,
+    pub star: Option<bool>,
+    /// For label streams, also include labels nested below it (`name/child`).
+    pub include_subdirectories: bool,
+    /// Arrival time and id of the last item of the previous page.
+    pub continuation: Option<(DateTime<Utc>, i64)>,
+}
+
+/// Unread items of a stream and when its newest item arrived.
+pub struct UnreadCount {
+    pub count: i64,
+    pub newest: Option<DateTime<Utc>>,
+}
+
+pub struct ItemRef {
+    pub id: i64,
+    pub feed_id: i64,
+    pub crawled_at: DateTime<Utc>,
+}
+
+impl StreamFilter {
+    pub fn new(stream: Stream) -> StreamFilter {
+        StreamFilter {
+            stream,
+            count: 20,
+            oldest_first: false,
+            start_time: None,
+            end_time: None,
+            read: None,
+            star: None,
+            include_subdirectories: false,
+            continuation: None,
+        }
+    }
+
+    pub async fn item_refs(&self, pool: &PgPool) -> Result<Vec<ItemRef>> {
+        let (feed_id, label) = match &self.stream {
+            Stream::Feed(id) => (Some(*id), None),
+            Stream::Label(name) => (None, Some(name.as_str())),
+            _ => (None, None),
+        };
+        let read = match self.stream {
+            Stream::Read => Some(true),
+            _ => self.read,
+        };
+        let star = match self.stream {
+            Stream::Starred => Some(true),
+            _ => self.star,
+        };
+        let continuation = self.continuation.map(|(crawled_at, _)| crawled_at);
+        let continuation_id = self.continuation.map(|(_, id)| id);
+        // One query per direction, so that both can walk the (crawled_at, id) index.
+        macro_rules! item_refs {
+            ($after:literal, $order:literal) => {
+                query_as!(
+                    ItemRef,
+                    "select items.id, items.feed_id, items.crawled_at from items
+                    where ($1::bigint is null or items.feed_id = $1)
+                    and ($2::text is null or items.feed_id in (
+                        select taggings.feed_id from taggings join tags on tags.id = taggings.tag_id
+                        where tags.name = $2 or ($3 and starts_with(tags.name, $2 || '/')))
+                    or items.id in (
+                        select item_taggings.item_id from item_taggings join tags on tags.id = item_taggings.tag_id
+                        where tags.name = $2 or ($3 and starts_with(tags.name, $2 || '/'))))
+                    and ($4::bool is null or items.read = $4)
+                    and ($5::bool is null or items.star = $5)
+                    and ($6::timestamptz is null or items.crawled_at >= $6)
+                    and ($7::timestamptz is null or items.crawled_at <= $7)
+                    and ($8::timestamptz is null or (items.crawled_at, items.id) "
+                        + $after
+                        + " ($8, $9::bigint))
+                    order by items.crawled_at "
+                        + $order
+                        + ", items.id "
+                        + $order
+                        + " limit $10",
+                    feed_id,
+                    label,
+                    self.include_subdirectories,
+                    read,
+                    star,
+                    self.start_time,
+                    self.end_time,
+                    continuation,
+                    continuation_id,
+                    self.count
+                )
+                .fetch_all(pool)
+                .await?
+            };
+        }
+        let refs = match self.oldest_first {
+            true => item_refs!(">", "asc"),
+            false => item_refs!("<", "desc"),
+        };
+        Ok(refs)
+    }
+}
@@ -1,5 +1,6 @@
 use crate::error::Result;
-use crate::model::feed::Feed;
+use crate::model::{feed::Feed, stream::UnreadCount};
+use chrono::{DateTime, Utc};
 use sqlx::*;
 
 #[derive(FromRow)]
@@ -20,6 +21,21 @@ impl Tag {
         })
     }
 
+    /// The tag called `name`, created if it does not exist yet.
+    pub async fn get_or_create(pool: &PgPool, name: &str) -> Result<Tag> {
+        let id = query!(
+            "insert into tags (name) values ($1) on conflict (name) do update set name = excluded.name returning id",
+            name
+        )
+        .fetch_one(pool)
+        .await?
+        .id;
+        Ok(Tag {
+            id,
+            name: name.to_string(),
+        })
+    }
+
     pub async fn tags(pool: &PgPool) -> Result<Vec<Tag>> {
         let tags = query_as!(Tag, "select id, name from tags")
             .fetch_all(pool)
@@ -27,17 +43,125 @@ impl Tag {
         Ok(tags)
     }
 
+    pub async fn get_tag_by_name(pool: &PgPool, name: &str) -> Result<Tag> {
+        let tag = query_as!(Tag, "select id, name from tags where name = $1", name)
+            .fetch_one(pool)
+            .await?;
+        Ok(tag)
+    }
+
+    /// Rename the tag. If a tag called `name` already exists, this tag is
+    /// merged into it and the existing tag is returned.
+    pub async fn rename(self, pool: &PgPool, name: &str) -> Result<Tag> {
+        let mut tx = pool.begin().await?;
+        let existing = query_as!(Tag, "select id, name from tags where name = $1", name)
+            .fetch_optional(&mut *tx)
+            .await?;
+        let tag = match existing {
+            Some(existing) if existing.id != self.id => {
+                query!(
+                    "insert into taggings (feed_id, tag_id) select feed_id, $2 from taggings where tag_id = $1 on conflict do nothing",
+                    self.id,
+                    existing.id
+                )
+                .execute(&mut *tx)
+                .await?;
+                query!(
+                    "insert into item_taggings (item_id, tag_id) select item_id, $2 from item_taggings where tag_id = $1 on conflict do nothing",
+                    self.id,
+                    existing.id
+                )
+                .execute(&mut *tx)
+                .await?;
+                query!("delete from tags where id = $1", self.id)
+                    .execute(&mut *tx)
+                    .await?;
+                existing
+            }
+            _ => {
+                query!("update tags set name = $1 where id = $2", name, self.id)
+                    .execute(&mut *tx)
+                    .await?;
+                Tag {
+                    id: self.id,
+                    name: name.to_string(),
+                }
+            }
+        };
+        tx.commit().await?;
+        Ok(tag)
+    }
+
+    /// Delete the tag. Feeds and items carrying it are kept.
+    pub async fn delete(&self, pool: &PgPool) -> Result<()> {
+        query!("delete from tags where id = $1", self.id)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+
     pub async fn feeds(&self, pool: &PgPool) -> Result<Vec<Feed>> {
-        let feeds = query_as!(Feed, "select id, title, feed_uri, site_uri, updated_at from taggings join feeds on (id = feed_id) where tag_id = $1", self.id).fetch_all(pool).await?;
+        let feeds = query_as!(Feed, "select id, title, feed_uri, site_uri, updated_at, last_fetched_at, next_fetch_at, fetch_interval, etag, last_modified, last_success_at, last_error, last_error_kind, consecutive_failures, full_text from taggings join feeds on (id = feed_id) where tag_id = $1", self.id).fetch_all(pool).await?;
         Ok(feeds)
     }
 
     pub async fn unread_count(&self, pool: &PgPool) -> Result<i64> {
-        let feeds = self.feeds(pool).await?;
-        let mut count: i64 = 0;
-        for feed in feeds {
-            count = count + feed.unread_count(pool).await?
-        }
+        let count = query!(
+            "select count(id) as \"count!\" from items where read = false and (
+                feed_id in (select feed_id from taggings where tag_id = $1)
+                or id in (select item_id from item_taggings where tag_id = $1))",
+            self.id
+        )
+        .fetch_one(pool)
+        .await?
+        .count;
         Ok(count)
     }
-}
+
+    /// Unread count of every tag, over items of its feeds and items labelled directly.
+    pub async fn unread_counts(pool: &PgPool) -> Result<Vec<(Tag, UnreadCount)>> {
+        let counts = query!(
+            "select tags.id, tags.name, count(tagged.item_id) filter (where tagged.read = false) as \"count!\", max(tagged.crawled_at) as newest
+            from tags left join (
+                select taggings.tag_id, items.id as item_id, items.read, items.crawled_at
+                from taggings join items on items.feed_id = taggings.feed_id
+                union
+                select item_taggings.tag_id, items.id, items.read, items.crawled_at
+                from item_taggings join items on items.id = item_taggings.item_id
+            ) as tagged on tagged.tag_id = tags.id
+            group by tags.id"
+        )
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .map(|row| {
+            (
+                Tag {
+                    id: row.id,
+                    name: row.name,
+                },
+                UnreadCount {
+                    count: row.count,
+                    newest: row.newest,
+                },
+            )
+        })
+        .collect();
+        Ok(counts)
+    }
+
+    /// Mark read every item labelled with this tag, through its feeds or directly,
+    /// that arrived before `before`.
+    pub async fn mark_read(&self, pool: &PgPool, before: DateTime<Utc>) -> Result<()> {
+        query!(
+            "update items set read = true where read = false and crawled_at <= $2 and (
+                feed_id in (select feed_id from taggings where tag_id = $1)
+                or id in (select item_id from item_taggings where tag_id = $1))",
+            self.id,
+            before
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+}
\ No newline at end of file
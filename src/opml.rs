@@ -0,0 +1,227 @@
+use crate::error::Result;
+use crate::model::{feed::Feed, tag::Tag};
+use futures::{stream, StreamExt};
+use opml::{Head, Outline, OPML};
+use reqwest::Client;
+use sqlx::PgPool;
+
+/// How many new feeds are fetched at once while importing.
+const IMPORT_CONCURRENCY: usize = 8;
+
+pub enum ImportStatus {
+    Imported(Box<Feed>),
+    Exists,
+    Failed(String),
+}
+
+pub struct ImportResult {
+    pub uri: String,
+    pub title: String,
+    pub status: ImportStatus,
+}
+
+/// Export every feed as OPML 2.0, with tags as (nested) folders.
+pub async fn export(pool: &PgPool) -> Result<String> {
+    let mut opml = OPML {
+        head: Some(Head {
+            title: Some("Liu subscriptions".to_string()),
+            ..Head::default()
+        }),
+        ..OPML::default()
+    };
+    for feed in Feed::feeds(pool).await? {
+        let outline = Outline {
+            text: feed.title.clone(),
+            title: Some(feed.title.clone()),
+            r#type: Some("rss".to_string()),
+            xml_url: Some(feed.feed_uri.clone()),
+            html_url: feed.site_uri.clone(),
+            ..Outline::default()
+        };
+        let tags = feed.tags(pool).await?;
+        if tags.is_empty() {
+            opml.body.outlines.push(outline);
+            continue;
+        }
+        for tag in tags {
+            folder(&mut opml.body.outlines, &tag.name).push(outline.clone());
+        }
+    }
+    Ok(format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n{}",
+        opml.to_string()?
+    ))
+}
+
+/// Subscribe to every feed of an OPML document. Folders become tags, joined
+/// with `/` when nested, and are added to feeds already subscribed to.
+pub async fn import(pool: &PgPool, client: &Client, src: &str) -> Result<Vec<ImportResult>> {
+    let opml = OPML::from_str(src)?;
+    let mut feeds = Vec::new();
+    let mut labels = Vec::new();
+    collect(&opml.body.outlines, None, &mut feeds, &mut labels);
+    for label in labels.iter() {
+        Tag::get_or_create(pool, label).await?;
+    }
+    let results = stream::iter(feeds)
+        .map(|(uri, title, labels)| import_feed(pool, client, uri, title, labels))
+        .buffer_unordered(IMPORT_CONCURRENCY)
+        .collect()
+        .await;
+    Ok(results)
+}
+
+async fn import_feed(pool: &PgPool, client: &Client, uri: String, title: String, labels: Vec<String>) -> ImportResult {
+    let status = match Feed::get_feed_by_uri(pool, &uri).await {
+        // Feeds already subscribed to still join the folders they are listed in.
+        Ok(feed) => match add_labels(pool, &feed, &labels).await {
+            Ok(()) => ImportStatus::Exists,
+            Err(err) => ImportStatus::Failed(err.to_string()),
+        },
+        Err(err) if !err.is_not_found() => ImportStatus::Failed(err.to_string()),
+        Err(_) => match subscribe(pool, client, &uri, &title, &labels).await {
+            Ok(feed) => ImportStatus::Imported(Box::new(feed)),
+            Err(err) => ImportStatus::Failed(err.to_string()),
+        },
+    };
+    ImportResult { uri, title, status }
+}
+
+async fn subscribe(pool: &PgPool, client: &Client, uri: &str, title: &str, labels: &[String]) -> Result<Feed> {
+    let mut feed = Feed::add_and_update_feed(pool, client, uri).await?;
+    if feed.title.is_empty() && !title.is_empty() {
+        feed.title = title.to_string();
+        feed.save(pool).await?;
+    }
+    add_labels(pool, &feed, labels).await?;
+    Ok(feed)
+}
+
+async fn add_labels(pool: &PgPool, feed: &Feed, labels: &[String]) -> Result<()> {
+    for label in labels {
+        feed.add_tag(Tag::get_or_create(pool, label).await?, pool).await?;
+    }
+    Ok(())
+}
+
+/// Gather `(uri, title, labels)` of every feed below `outlines`, merging feeds
+/// listed in several folders, and the label of every folder.
+fn collect(
+    outlines: &[Outline],
+    parent: Option<&str>,
+    feeds: &mut Vec<(String, String, Vec<String>)>,
+    labels: &mut Vec<String>,
+) {
+    for outline in outlines {
+        let name = outline.title.as_deref().unwrap_or(&outline.text);
+        match &outline.xml_url {
+            Some(uri) => {
+                let position = feeds.iter().position(|(u, _, _)| u == uri);
+                let feed = match position {
+                    Some(position) => &mut feeds[position],
+                    None => {
+                        feeds.push((uri.clone(), name.to_string(), Vec::new()));
+                        feeds.last_mut().unwrap()
+                    }
+                };
+                if let Some(parent) = parent {
+                    if !feed.2.iter().any(|l| l == parent) {
+                        feed.2.push(parent.to_string());
+                    }
+                }
+            }
+            None => {
+                let label = match parent {
+                    Some(parent) => format!("{}/{}", parent, name),
+                    None => name.to_string(),
+                };
+                if !labels.contains(&label) {
+                    labels.push(label.clone());
+                }
+                collect(&outline.outlines, Some(&label), feeds, labels);
+            }
+        }
+    }
+}
+
+/// The outlines of the folder for `label`, creating folders along its path.
+fn folder<'a>(mut outlines: &'a mut Vec<Outline>, label: &str) -> &'a mut Vec<Outline> {
+    for name in label.split('/') {
+        let position = outlines
+            .iter()
+            .position(|o| o.xml_url.is_none() && o.text == name);
+        let position = match position {
+            Some(position) => position,
+            None => {
+                outlines.push(Outline {
+                    text: name.to_string(),
+                    title: Some(name.to_string()),
+                    ..Outline::default()
+                });
+                outlines.len() - 1
+            }
+        };
+        outlines = &mut outlines[position].outlines;
+    }
+    outlines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collects_feeds_and_nested_folders() {
+        let opml = OPML::from_str(
+            r#"<opml version="2.0"><head/><body>
+                <outline text="Loose" xmlUrl="http://a.example/feed"/>
+                <outline text="News">
+                    <outline text="A" xmlUrl="http://b.example/feed"/>
+                    <outline text="Tech">
+                        <outline text="C" title="Cee" xmlUrl="http://c.example/feed"/>
+                    </outline>
+                </outline>
+                <outline text="Later">
+                    <outline text="B again" xmlUrl="http://b.example/feed"/>
+                </outline>
+            </body></opml>"#,
+        )
+        .unwrap();
+        let mut feeds = Vec::new();
+        let mut labels = Vec::new();
+        collect(&opml.body.outlines, None, &mut feeds, &mut labels);
+        assert_eq!(labels, ["News", "News/Tech", "Later"]);
+        assert_eq!(
+            feeds,
+            [
+                ("http://a.example/feed".to_string(), "Loose".to_string(), vec![]),
+                (
+                    "http://b.example/feed".to_string(),
+                    "A".to_string(),
+                    vec!["News".to_string(), "Later".to_string()]
+                ),
+                (
+                    "http://c.example/feed".to_string(),
+                    "Cee".to_string(),
+                    vec!["News/Tech".to_string()]
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn creates_folders_along_a_label() {
+        let mut outlines = Vec::new();
+        folder(&mut outlines, "News/Tech").push(Outline {
+            text: "C".to_string(),
+            xml_url: Some("http://c.example/feed".to_string()),
+            ..Outline::default()
+        });
+        folder(&mut outlines, "News").push(Outline::default());
+        assert_eq!(outlines.len(), 1);
+        assert_eq!(outlines[0].text, "News");
+        assert_eq!(outlines[0].outlines.len(), 2);
+        assert_eq!(outlines[0].outlines[0].text, "Tech");
+        assert_eq!(outlines[0].outlines[0].outlines[0].text, "C");
+    }
+}
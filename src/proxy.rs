@@ -0,0 +1,297 @@
+use crate::error::{AppError, Result};
+use crate::http::{check_status, is_public_url, public_client, read_body};
+use crate::sanitize::{rewrite_media, Media};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use md5::{Digest, Md5};
+use rand::Rng;
+use reqwest::Url;
+use sha2::Sha256;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Largest response the proxy downloads and serves.
+const MAX_SIZE: usize = 16 * 1024 * 1024;
+/// Prefix of the content types the proxy downloads and caches.
+const ALLOWED_TYPE: &str = "image/";
+/// Prefixes of the content types the proxy streams. These are too large to
+/// download whole and need range requests to seek, so they are not cached.
+const STREAMED_TYPES: [&str; 2] = ["video/", "audio/"];
+/// How long a streamed response may last, past the usual request timeout.
+const STREAM_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+/// Age after which leftovers of interrupted cache writes are deleted.
+const STALE_TMP_AGE: Duration = Duration::from_secs(60 * 60);
+
+/// Which media URLs of item content go through the proxy.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ProxyMode {
+    Off,
+    /// Only plain `http:` URLs, which browsers block or flag on an HTTPS page.
+    OnlyInsecure,
+    All,
+}
+
+impl std::str::FromStr for ProxyMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<ProxyMode, String> {
+        match s {
+            "off" => Ok(ProxyMode::Off),
+            "only-insecure" => Ok(ProxyMode::OnlyInsecure),
+            "all" => Ok(ProxyMode::All),
+            _ => Err(format!("Unknown proxy mode {}", s)),
+        }
+    }
+}
+
+/// Serves remote images and videos from this server, so that readers neither load
+/// mixed content nor reveal themselves to the sites of their feeds. Proxied
+/// URLs are signed, which keeps the proxy from being open to anyone, and only
+/// public addresses are fetched.
+#[derive(Clone)]
+pub struct Proxy {
+    pub mode: ProxyMode,
+    key: Vec<u8>,
+    cache_dir: PathBuf,
+    client: reqwest::Client,
+}
+
+impl Proxy {
+    pub fn new(mode: ProxyMode, key: &[u8], cache_dir: impl Into<PathBuf>) -> Proxy {
+        Proxy {
+            mode,
+            key: key.to_vec(),
+            cache_dir: cache_dir.into(),
+            client: public_client(),
+        }
+    }
+
+    /// The proxy URL of the image `url` below `origin` (empty for links on this
+    /// site), or `url` itself when the mode leaves it alone.
+    pub fn url(&self, origin: &str, url: &str) -> String {
+        self.signed_url(&format!("{}/proxy", origin), url)
+    }
+
+    /// Like `url`, for a video or audio file, which is streamed rather than cached.
+    pub fn media_url(&self, origin: &str, url: &str) -> String {
+        self.signed_url(&format!("{}/proxy/media", origin), url)
+    }
+
+    fn signed_url(&self, prefix: &str, url: &str) -> String {
+        let proxied = match self.mode {
+            ProxyMode::Off => false,
+            ProxyMode::OnlyInsecure => url.starts_with("http://"),
+            ProxyMode::All => url.starts_with("http://") || url.starts_with("https://"),
+        };
+        if !proxied {
+            return url.to_string();
+        }
+        format!(
+            "{}/{}.{}",
+            prefix,
+            URL_SAFE_NO_PAD.encode(self.mac(url).finalize().into_bytes()),
+            URL_SAFE_NO_PAD.encode(url)
+        )
+    }
+
+    /// Rewrite the image and video sources of sanitized `html` to proxy URLs.
+    pub fn rewrite(&self, origin: &str, html: &str) -> String {
+        if self.mode == ProxyMode::Off {
+            return html.to_string();
+        }
+        let proxy = self.clone();
+        let origin = origin.to_string();
+        rewrite_media(html, move |media, url| match media {
+            Media::Image => proxy.url(&origin, url),
+            Media::Video => proxy.media_url(&origin, url),
+        })
+    }
+
+    /// The URL signed into `token`, if the signature is valid.
+    pub fn verify(&self, token: &str) -> Option<String> {
+        let (signature, url) = token.split_once('.')?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+        let url = String::from_utf8(URL_SAFE_NO_PAD.decode(url).ok()?).ok()?;
+        self.mac(&url).verify_slice(&signature).ok()?;
+        Some(url)
+    }
+
+    /// Content type and body of `url`, from the disk cache when possible.
+    /// `None` when the URL is not public or the response is too large or
+    /// not an image.
+    pub async fn fetch(&self, url: &str) -> Result<Option<(String, Vec<u8>)>> {
+        if !Url::parse(url).map(|url| is_public_url(&url)).unwrap_or(false) {
+            return Ok(None);
+        }
+        let name = format!("{:x}", Md5::digest(url));
+        let path = self.cache_dir.join(&name);
+        let type_path = self.cache_dir.join(format!("{}.type", name));
+        // The type is written last, so its presence means the body is complete.
+        if let Ok(content_type) = tokio::fs::read_to_string(&type_path).await {
+            if let Ok(body) = tokio::fs::read(&path).await {
+                return Ok(Some((content_type, body)));
+            }
+        }
+        let response = check_status(self.client.get(url).send().await?)?;
+        let content_type = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|t| t.to_str().ok())
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        if !content_type.starts_with(ALLOWED_TYPE) {
+            return Ok(None);
+        }
+        let body = match read_body(response, MAX_SIZE).await {
+            Err(AppError::TooLarge(_)) => return Ok(None),
+            body => body?,
+        };
+        // Failing to cache only costs another download next time.
+        let _ = self.store(&path, &type_path, &content_type, &body).await;
+        Ok(Some((content_type, body)))
+    }
+
+    /// The response to a request for the video or audio file at `url`,
+    /// with the `Range` and `If-Range` of the reader's request. `None` when
+    /// the URL is not public or the response is not video or audio.
+    pub async fn stream(
+        &self,
+        url: &str,
+        range: Option<&[u8]>,
+        if_range: Option<&[u8]>,
+    ) -> Result<Option<reqwest::Response>> {
+        if !Url::parse(url).map(|url| is_public_url(&url)).unwrap_or(false) {
+            return Ok(None);
+        }
+        let mut request = self.client.get(url).timeout(STREAM_TIMEOUT);
+        for (name, value) in [(reqwest::header::RANGE, range), (reqwest::header::IF_RANGE, if_range)] {
+            if let Some(value) = value {
+                request = request.header(name, value);
+            }
+        }
+        let response = check_status(request.send().await?)?;
+        let streamed = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|t| t.to_str().ok())
+            .map(|t| t.to_ascii_lowercase())
+            .map(|t| STREAMED_TYPES.iter().any(|prefix| t.starts_with(prefix)))
+            .unwrap_or(false);
+        Ok(streamed.then_some(response))
+    }
+
+    async fn store(&self, path: &Path, type_path: &Path, content_type: &str, body: &[u8]) -> std::io::Result<()> {
+        tokio::fs::create_dir_all(&self.cache_dir).await?;
+        write_atomically(path, body).await?;
+        write_atomically(type_path, content_type.as_bytes()).await
+    }
+
+    /// Delete the oldest downloads until the cache holds at most `max_size`
+    /// bytes, and leftovers of interrupted writes.
+    pub async fn prune(&self, max_size: u64) -> std::io::Result<()> {
+        let mut dir = match tokio::fs::read_dir(&self.cache_dir).await {
+            Ok(dir) => dir,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err),
+        };
+        let mut bodies = Vec::new();
+        while let Some(entry) = dir.next_entry().await? {
+            let metadata = entry.metadata().await?;
+            let modified = metadata.modified()?;
+            let path = entry.path();
+            match path.extension().and_then(|e| e.to_str()) {
+                Some("type") => {}
+                Some("tmp") => {
+                    let age = SystemTime::now().duration_since(modified).unwrap_or_default();
+                    if age > STALE_TMP_AGE {
+                        tokio::fs::remove_file(&path).await?;
+                    }
+                }
+                _ => bodies.push((modified, metadata.len(), path)),
+            }
+        }
+        bodies.sort();
+        let mut size = bodies.iter().map(|(_, len, _)| len).sum::<u64>();
+        for (_, len, path) in bodies {
+            if size <= max_size {
+                break;
+            }
+            // The type goes first, as its presence marks a complete download.
+            let _ = tokio::fs::remove_file(path.with_extension("type")).await;
+            tokio::fs::remove_file(&path).await?;
+            size -= len;
+        }
+        Ok(())
+    }
+
+    fn mac(&self, url: &str) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC takes keys of any length");
+        mac.update(url.as_bytes());
+        mac
+    }
+}
+
+/// Write through a uniquely named temporary file, so that readers and
+/// concurrent downloads of the same URL never see a partial file.
+async fn write_atomically(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let tmp = path.with_extension(format!("{:016x}.tmp", rand::thread_rng().gen::<u64>()));
+    tokio::fs::write(&tmp, contents).await?;
+    tokio::fs::rename(&tmp, path).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn proxy(mode: ProxyMode) -> Proxy {
+        Proxy::new(mode, b"key", "cache")
+    }
+
+    #[test]
+    fn signs_urls_that_verify() {
+        let proxy = proxy(ProxyMode::All);
+        let url = proxy.url("http://liu", "https://example.com/a.png?x=1");
+        let token = url.strip_prefix("http://liu/proxy/").unwrap();
+        assert_eq!(proxy.verify(token).as_deref(), Some("https://example.com/a.png?x=1"));
+    }
+
+    #[test]
+    fn rejects_tampered_tokens() {
+        let proxy = proxy(ProxyMode::All);
+        let url = proxy.url("", "https://example.com/a.png");
+        let (signature, _) = url.strip_prefix("/proxy/").unwrap().split_once('.').unwrap();
+        let other = URL_SAFE_NO_PAD.encode("http://127.0.0.1/secret");
+        assert_eq!(proxy.verify(&format!("{}.{}", signature, other)), None);
+        let forged = URL_SAFE_NO_PAD.encode(proxy.mac("https://example.com/b.png").finalize().into_bytes());
+        assert_eq!(proxy.verify(&format!("{}.{}", forged, other)), None);
+        assert_eq!(proxy.verify("not-a-token"), None);
+        let token = url.strip_prefix("/proxy/").unwrap();
+        assert_eq!(Proxy::new(ProxyMode::All, b"other", "cache").verify(token), None);
+    }
+
+    #[test]
+    fn rewrites_images_and_videos() {
+        let proxy = proxy(ProxyMode::All);
+        let html = proxy.rewrite(
+            "",
+            r#"<img src="https://example.com/a.png"><video src="https://example.com/v.mp4" poster="https://example.com/p.jpg"><source src="https://example.com/v.webm"></video>"#,
+        );
+        for url in ["https://example.com/a.png", "https://example.com/p.jpg"] {
+            assert!(html.contains(&proxy.url("", url)), "{}", html);
+        }
+        for url in ["https://example.com/v.mp4", "https://example.com/v.webm"] {
+            assert!(html.contains(&proxy.media_url("", url)), "{}", html);
+        }
+        assert!(!html.contains("https://example.com"));
+    }
+
+    #[test]
+    fn proxies_according_to_mode() {
+        let url = |mode, url| proxy(mode).url("", url);
+        assert_eq!(url(ProxyMode::Off, "http://example.com/a.png"), "http://example.com/a.png");
+        assert!(url(ProxyMode::OnlyInsecure, "http://example.com/a.png").starts_with("/proxy/"));
+        assert_eq!(url(ProxyMode::OnlyInsecure, "https://example.com/a.png"), "https://example.com/a.png");
+        assert!(url(ProxyMode::All, "https://example.com/a.png").starts_with("/proxy/"));
+        assert_eq!(url(ProxyMode::All, "data:image/png;base64,AA"), "data:image/png;base64,AA");
+    }
+}
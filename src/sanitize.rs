@@ -0,0 +1,171 @@
+use ammonia::{Builder, UrlRelative};
+use std::borrow::Cow;
+use url::Url;
+
+/// Clean feed HTML with an allowlist: formatting markup is kept while scripts,
+/// event handlers, `javascript:` URLs and other active content are dropped.
+/// Links get `rel="noopener noreferrer"` and images and videos load lazily.
+/// Relative URLs, `srcset` included, are resolved against `base` when given.
+pub fn sanitize(html: &str, base: Option<&Url>) -> String {
+    let mut builder = builder();
+    if let Some(base) = base.cloned() {
+        builder
+            .url_relative(UrlRelative::RewriteWithBase(base.clone()))
+            .attribute_filter(move |_, attribute, value| match attribute {
+                "srcset" => Some(Cow::Owned(map_srcset(value, |url| resolve(Some(&base), url)))),
+                _ => Some(Cow::Borrowed(value)),
+            });
+    }
+    builder.clean(html).to_string()
+}
+
+/// What a media URL of item content loads.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Media {
+    Image,
+    /// Video or audio, played from `<video>` or `<source>`.
+    Video,
+}
+
+/// Pass the sources of every image and video, posters included, of sanitized
+/// `html` through `rewrite`.
+pub fn rewrite_media<F>(html: &str, rewrite: F) -> String
+where
+    F: Fn(Media, &str) -> String + Send + Sync + 'static,
+{
+    let mut builder = builder();
+    builder.attribute_filter(move |element, attribute, value| match (element, attribute) {
+        ("img", "src") | ("video", "poster") => Some(Cow::Owned(rewrite(Media::Image, value))),
+        ("video" | "source", "src") => Some(Cow::Owned(rewrite(Media::Video, value))),
+        ("img" | "source", "srcset") => Some(Cow::Owned(map_srcset(value, |url| rewrite(Media::Image, url)))),
+        _ => Some(Cow::Borrowed(value)),
+    });
+    builder.clean(html).to_string()
+}
+
+fn builder() -> Builder<'static> {
+    let mut builder = Builder::default();
+    builder
+        .add_tags(["audio", "video", "source", "picture"])
+        .add_tag_attributes("img", ["srcset", "sizes"])
+        .add_tag_attributes("audio", ["src", "controls"])
+        .add_tag_attributes("video", ["src", "poster", "controls", "width", "height"])
+        .add_tag_attributes("source", ["src", "srcset", "type", "media", "sizes"])
+        .link_rel(Some("noopener noreferrer"))
+        // At most one value set per tag: ammonia writes several in random
+        // order, which would make cleaning the same HTML twice disagree.
+        .set_tag_attribute_value("img", "loading", "lazy")
+        .set_tag_attribute_value("video", "preload", "none")
+        .set_tag_attribute_value("audio", "preload", "none");
+    builder
+}
+
+/// Resolve `href` against `base`, leaving it alone if either is unusable.
+pub fn resolve(base: Option<&Url>, href: &str) -> String {
+    base.and_then(|base| base.join(href.trim()).ok())
+        .map(|url| url.to_string())
+        .unwrap_or_else(|| href.to_string())
+}
+
+/// `href` resolved against `base`, if that gives an `http:` or `https:` URL.
+/// Links and media of feeds are kept to those, so that no `javascript:` or
+/// other active URL ends up in an `href` or `src`.
+pub fn http_url(base: Option<&Url>, href: &str) -> Option<String> {
+    let url = match base {
+        Some(base) => base.join(href.trim()).ok()?,
+        None => Url::parse(href.trim()).ok()?,
+    };
+    matches!(url.scheme(), "http" | "https").then(|| url.to_string())
+}
+
+/// Map the URL of every image candidate (`url [descriptor]`) of a `srcset`.
+/// As in the HTML spec, a URL runs up to whitespace, so that it may contain
+/// commas; only a trailing comma ends a candidate without descriptor.
+fn map_srcset(srcset: &str, map: impl Fn(&str) -> String) -> String {
+    let mut candidates = Vec::new();
+    let mut rest = srcset;
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == ',');
+        if rest.is_empty() {
+            break;
+        }
+        let (url, after) = rest.split_at(rest.find(char::is_whitespace).unwrap_or(rest.len()));
+        if url.ends_with(',') {
+            candidates.push(map(url.trim_end_matches(',')));
+            rest = after;
+            continue;
+        }
+        let (descriptor, after) = after.split_at(after.find(',').unwrap_or(after.len()));
+        candidates.push(match descriptor.trim() {
+            "" => map(url),
+            descriptor => format!("{} {}", map(url), descriptor),
+        });
+        rest = after;
+    }
+    candidates.join(", ")
+}
+
+/// Escape plain text for use as HTML.
+pub fn escape(text: &str) -> String {
+    ammonia::clean_text(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn base() -> Url {
+        Url::parse("http://example.com/posts/1").unwrap()
+    }
+
+    #[test]
+    fn removes_active_content() {
+        let html = r#"<p onclick="steal()">Hi<script>steal()</script></p><a href="javascript:steal()">x</a><iframe src="http://evil"></iframe>"#;
+        assert_eq!(sanitize(html, None), r#"<p>Hi</p><a rel="noopener noreferrer">x</a>"#);
+    }
+
+    #[test]
+    fn resolves_relative_urls() {
+        let html = r#"<a href="../about">a</a><img src="/i.png" srcset="a.png 1x, /b.png 2x">"#;
+        assert_eq!(
+            sanitize(html, Some(&base())),
+            concat!(
+                r#"<a href="http://example.com/about" rel="noopener noreferrer">a</a>"#,
+                r#"<img src="http://example.com/i.png" srcset="http://example.com/posts/a.png 1x, http://example.com/b.png 2x" loading="lazy">"#
+            )
+        );
+    }
+
+    #[test]
+    fn keeps_only_http_urls() {
+        assert_eq!(http_url(Some(&base()), "2").as_deref(), Some("http://example.com/posts/2"));
+        assert_eq!(http_url(None, " https://example.com/a ").as_deref(), Some("https://example.com/a"));
+        assert_eq!(http_url(Some(&base()), "javascript:alert(1)"), None);
+        assert_eq!(http_url(Some(&base()), "JavaScript:alert(1)"), None);
+        assert_eq!(http_url(Some(&base()), "data:text/html,<script>alert(1)</script>"), None);
+        assert_eq!(http_url(None, "/relative"), None);
+    }
+
+    #[test]
+    fn resolves_against_a_base() {
+        assert_eq!(resolve(Some(&base()), "../a b.png"), "http://example.com/a%20b.png");
+        assert_eq!(resolve(Some(&base()), " //cdn.example.com/x "), "http://cdn.example.com/x");
+        assert_eq!(resolve(Some(&base()), "https://other.example/y"), "https://other.example/y");
+        assert_eq!(resolve(None, "a.png"), "a.png");
+    }
+
+    #[test]
+    fn maps_srcset_candidates() {
+        let upper = |url: &str| url.to_uppercase();
+        assert_eq!(map_srcset("a.png 1x, b.png 2x", upper), "A.PNG 1x, B.PNG 2x");
+        assert_eq!(map_srcset(" a.png 100w ,b.png   200w ", upper), "A.PNG 100w, B.PNG 200w");
+        assert_eq!(map_srcset("a.png", upper), "A.PNG");
+        assert_eq!(map_srcset("a.png,b.png 2x", upper), "A.PNG,B.PNG 2x");
+        assert_eq!(map_srcset("a.png, b.png 2x", upper), "A.PNG, B.PNG 2x");
+        assert_eq!(
+            map_srcset("https://img.example/c_fill,w_100/a.jpg 1x, https://img.example/c_fill,w_200/a.jpg 2x", upper),
+            "HTTPS://IMG.EXAMPLE/C_FILL,W_100/A.JPG 1x, HTTPS://IMG.EXAMPLE/C_FILL,W_200/A.JPG 2x"
+        );
+        assert_eq!(map_srcset("", upper), "");
+    }
+}
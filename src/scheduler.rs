@@ -0,0 +1,53 @@
+use crate::model::feed::Feed;
+use futures::{future, stream, StreamExt};
+use sqlx::PgPool;
+use std::time::Duration;
+use tokio::sync::watch;
+
+/// How often the scheduler looks for feeds that are due.
+const TICK: Duration = Duration::from_secs(60);
+
+/// Periodically refreshes every feed whose next fetch is due.
+pub struct Scheduler {
+    pub pool: PgPool,
+    pub client: reqwest::Client,
+    /// Seconds between fetches of feeds without an interval of their own.
+    pub interval: i64,
+    /// How many feeds are fetched at once.
+    pub concurrency: usize,
+}
+
+impl Scheduler {
+    /// Run until `shutdown` is signalled (or its sender dropped). No new
+    /// fetches start after that, but those in flight are finished.
+    pub async fn run(self, mut shutdown: watch::Receiver<bool>) {
+        loop {
+            self.refresh(&shutdown).await;
+            tokio::select! {
+                _ = tokio::time::sleep(TICK) => {}
+                _ = shutdown.changed() => break,
+            }
+        }
+    }
+
+    async fn refresh(&self, shutdown: &watch::Receiver<bool>) {
+        let due = match Feed::due_feeds(&self.pool, self.interval).await {
+            Ok(due) => due,
+            Err(err) => {
+                log::error!("Failed to list feeds to update: {}", err);
+                return;
+            }
+        };
+        stream::iter(due)
+            .take_while(|_| future::ready(!*shutdown.borrow()))
+            .for_each_concurrent(self.concurrency, |mut feed| async move {
+                if let Err(err) = feed.update_feed(&self.pool, &self.client).await {
+                    log::warn!("Failed to update {}: {}", feed.feed_uri, err);
+                }
+                if let Err(err) = feed.schedule_fetch(&self.pool, self.interval).await {
+                    log::error!("Failed to schedule {}: {}", feed.feed_uri, err);
+                }
+            })
+            .await;
+    }
+}
@@ -1,48 +1,195 @@
 use crate::{
     app::State,
-    model::{feed::Feed, item::Item},
+    error::AppError,
+    model::{
+        feed::{Feed, Subscription},
+        icon::Icon,
+        item::Item,
+        session::{Session, AUTH_TOKEN_LIFETIME_DAYS},
+    },
+    opml::{self, ImportStatus},
+    proxy::Proxy,
 };
+use actix_multipart::Multipart;
+use actix_web::cookie::{time, Cookie, SameSite};
 use actix_web::*;
+use chrono::{DateTime, SecondsFormat, Utc};
+use futures::future::LocalBoxFuture;
+use futures::StreamExt;
 use handlebars::Handlebars;
 use serde::Deserialize;
 use serde_json::json;
 use sqlx::PgPool;
 
+/// Largest OPML file accepted for import.
+const MAX_OPML_SIZE: usize = 4 * 1024 * 1024;
+/// Cookie holding the auth token of a web session.
+const SESSION_COOKIE: &str = "liu_session";
+
+/// The session of a request signed in to the web interface. Pages taking it
+/// send everyone else to the login page; other requests get a 401.
+pub struct SignedIn(pub Session);
+
+impl FromRequest for SignedIn {
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, std::result::Result<Self, Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut dev::Payload) -> Self::Future {
+        let state = req.app_data::<web::Data<State<'static>>>().cloned();
+        let token = req.cookie(SESSION_COOKIE).map(|c| c.value().to_string());
+        let page = req.method() == http::Method::GET;
+        Box::pin(async move {
+            let session = match (state, token) {
+                (Some(state), Some(token)) => Session::get_session_by_token(&state.pool, &token)
+                    .await
+                    .ok()
+                    .flatten(),
+                _ => None,
+            };
+            let response = match page {
+                true => HttpResponse::SeeOther()
+                    .insert_header((http::header::LOCATION, "/login"))
+                    .finish(),
+                false => HttpResponse::Unauthorized().body("Sign in required"),
+            };
+            session
+                .map(SignedIn)
+                .ok_or_else(|| error::InternalError::from_response("Sign in required", response).into())
+        })
+    }
+}
+
 impl Feed {
     pub async fn render(&self, pool: &PgPool, hb: &Handlebars<'_>, template: &str) -> String {
         let mut items = Vec::new();
+        let mut newest = None;
         for i in self.items(pool).await.unwrap_or_default() {
+            newest = newest.max(Some(i.crawled_at));
             items.push(json!({
                 "id": i.id,
                 "title": i.title,
             }));
         }
+        let icon = Icon::get_icon(pool, self.id).await.ok().flatten().is_some();
         let data = json!({
             "id": self.id,
             "title": self.title,
-            "items": items
+            "icon": icon,
+            // Marking the feed read stops at the newest item on the page.
+            "newest": newest.map(|t| t.to_rfc3339_opts(SecondsFormat::Micros, true)),
+            "items": items,
+            "last_fetched_at": self.last_fetched_at.map(|t| t.to_rfc2822()),
+            "next_fetch_at": self.next_fetch_at.to_rfc2822(),
+            "fetch_interval": self.fetch_interval.map(|i| i / 60),
+            "full_text": self.full_text,
+            "last_success_at": self.last_success_at.map(|t| t.to_rfc2822()),
+            "last_error": self.last_error,
+            "last_error_kind": self.last_error_kind,
+            "consecutive_failures": self.consecutive_failures
         });
         hb.render(template, &data).unwrap()
     }
 }
 
 impl Item {
-    pub fn render(&self, hb: &Handlebars<'_>, template: &str) -> String {
+    pub async fn render(&self, pool: &PgPool, hb: &Handlebars<'_>, proxy: &Proxy, template: &str) -> String {
+        let mut enclosures = Vec::new();
+        for e in self.enclosures(pool).await.unwrap_or_default() {
+            let mime_type = e.mime_type.unwrap_or_default();
+            enclosures.push(json!({
+                "src": proxy.media_url("", &e.url),
+                "url": e.url,
+                "mime_type": mime_type,
+                "audio": mime_type.starts_with("audio/"),
+                "video": mime_type.starts_with("video/"),
+                "image": e.image.map(|image| proxy.url("", &image)),
+            }));
+        }
         let data = json!({
             "title": self.title,
-            "content": self.content
+            "link": self.link,
+            "content": match self.is_plain_text() {
+                true => self.body().to_string(),
+                false => proxy.rewrite("", self.body()),
+            },
+            "extracted": self.extracted_content.is_some(),
+            "plain_text": self.is_plain_text(),
+            // Content falls back to the summary, so only show a distinct one.
+            "summary": self
+                .summary
+                .as_ref()
+                .filter(|s| **s != self.content)
+                .map(|s| proxy.rewrite("", s)),
+            "categories": self.categories,
+            "thumbnail": self.thumbnail.as_ref().map(|t| proxy.url("", t)),
+            "id": self.id,
+            "enclosures": enclosures,
+            // Written into a script, so as a JSON literal rather than HTML text.
+            "playback_position": json!(self.playback_position.unwrap_or(0.0)).to_string()
         });
         hb.render(template, &data).unwrap()
     }
 }
 
+#[get("/login")]
+pub async fn login_page(state: web::Data<State<'_>>) -> impl Responder {
+    HttpResponse::Ok().body(state.hb.render("html/login", &json!({})).unwrap())
+}
+
+#[derive(Deserialize)]
+pub struct Login {
+    email: String,
+    password: String,
+}
+
+#[post("/login")]
+pub async fn login(req: HttpRequest, form: web::Form<Login>, state: web::Data<State<'_>>) -> impl Responder {
+    let token = match Session::login(&state.pool, &form.email, &form.password).await {
+        Ok(Some(token)) => token,
+        Ok(None) => {
+            let html = state.hb.render("html/login", &json!({ "failed": true })).unwrap();
+            return HttpResponse::Forbidden().body(html);
+        }
+        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+    };
+    let cookie = Cookie::build(SESSION_COOKIE, token)
+        .path("/")
+        .http_only(true)
+        // Also keeps other sites from submitting the forms on our behalf.
+        .same_site(SameSite::Strict)
+        .secure(req.connection_info().scheme() == "https")
+        .max_age(time::Duration::days(AUTH_TOKEN_LIFETIME_DAYS))
+        .finish();
+    HttpResponse::SeeOther()
+        .insert_header((http::header::LOCATION, "/"))
+        .cookie(cookie)
+        .finish()
+}
+
+#[post("/logout")]
+pub async fn logout(req: HttpRequest, state: web::Data<State<'_>>) -> impl Responder {
+    if let Some(cookie) = req.cookie(SESSION_COOKIE) {
+        if let Err(err) = Session::revoke_token(&state.pool, cookie.value()).await {
+            return HttpResponse::InternalServerError().body(err.to_string());
+        }
+    }
+    let mut cookie = Cookie::new(SESSION_COOKIE, "");
+    cookie.set_path("/");
+    cookie.make_removal();
+    HttpResponse::SeeOther()
+        .insert_header((http::header::LOCATION, "/login"))
+        .cookie(cookie)
+        .finish()
+}
+
 #[get("/")]
-pub async fn index(state: web::Data<State<'_>>) -> impl Responder {
+pub async fn index(_: SignedIn, state: web::Data<State<'_>>) -> impl Responder {
     let hb = &state.hb;
     let pool = &state.pool;
     let feeds = Feed::feeds(pool).await.unwrap_or_default();
+    let icons = Icon::feed_ids(pool).await.unwrap_or_default();
     let data = json!({
-        "feeds": feeds.iter().map(|f| json!({"id": f.id, "title": f.title})).collect::<Vec<_>>()
+        "feeds": feeds.iter().map(|f| json!({"id": f.id, "title": f.title, "icon": icons.contains(&f.id), "failing": f.consecutive_failures > 0})).collect::<Vec<_>>()
     });
     let html = hb.render("html/index", &data).unwrap();
     HttpResponse::Ok().body(html)
@@ -55,18 +202,28 @@ pub struct CreateFeed {
 
 #[post("/feeds")]
 pub async fn create_feed(
+    _: SignedIn,
     form: web::Form<CreateFeed>,
     state: web::Data<State<'_>>,
 ) -> impl Responder {
     let pool = &state.pool;
-    match Feed::add_and_update_feed(pool, &form.url).await {
-        Ok(feed) => "Ok".to_string(),
-        Err(e) => e.to_string(),
+    let hb = &state.hb;
+    match Feed::subscribe(pool, &state.client, &form.url).await {
+        Ok(Subscription::Subscribed(_)) => HttpResponse::Ok().body("Ok"),
+        Ok(Subscription::Choices(candidates)) => {
+            let data = json!({
+                "url": form.url,
+                "feeds": candidates.iter().map(|c| json!({"url": c.uri, "title": c.title})).collect::<Vec<_>>()
+            });
+            HttpResponse::Ok().body(hb.render("html/choose", &data).unwrap())
+        }
+        Err(e @ AppError::DBError(_)) => HttpResponse::InternalServerError().body(e.to_string()),
+        Err(e) => HttpResponse::BadRequest().body(e.to_string()),
     }
 }
 
 #[get("/feeds/{id}")]
-pub async fn get_feed(id: web::Path<i64>, state: web::Data<State<'_>>) -> impl Responder {
+pub async fn get_feed(_: SignedIn, id: web::Path<i64>, state: web::Data<State<'_>>) -> impl Responder {
     let pool = &state.pool;
     let hb = &state.hb;
     let f = Feed::get_feed_by_id(pool, *id).await.unwrap();
@@ -74,28 +231,284 @@ pub async fn get_feed(id: web::Path<i64>, state: web::Data<State<'_>>) -> impl R
 }
 
 #[get("/items/{id}")]
-pub async fn get_item(id: web::Path<i64>, state: web::Data<State<'_>>) -> impl Responder {
+pub async fn get_item(_: SignedIn, id: web::Path<i64>, state: web::Data<State<'_>>) -> impl Responder {
     let pool = &state.pool;
     let hb = &state.hb;
     let mut i = Item::get_item_by_id(pool, *id).await.unwrap();
     i.read = true;
     i.save(pool).await;
-    HttpResponse::Ok().body(i.render(hb, "html/item"))
+    HttpResponse::Ok().body(i.render(pool, hb, &state.proxy, "html/item").await)
+}
+
+#[post("/items/{id}/extract")]
+pub async fn extract_item(_: SignedIn, id: web::Path<i64>, state: web::Data<State<'_>>) -> impl Responder {
+    let pool = &state.pool;
+    let hb = &state.hb;
+    let mut i = match Item::get_item_by_id(pool, *id).await {
+        Ok(i) => i,
+        Err(_) => return HttpResponse::NotFound().body("Item not found"),
+    };
+    match i.extract_content(pool).await {
+        Ok(_) => HttpResponse::Ok().body(i.render(pool, hb, &state.proxy, "html/item").await),
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct PlaybackPosition {
+    position: f64,
+}
+
+#[post("/items/{id}/position")]
+pub async fn set_playback_position(
+    _: SignedIn,
+    id: web::Path<i64>,
+    form: web::Form<PlaybackPosition>,
+    state: web::Data<State<'_>>,
+) -> impl Responder {
+    if !form.position.is_finite() {
+        return HttpResponse::BadRequest().body("Invalid position");
+    }
+    let pool = &state.pool;
+    let mut i = match Item::get_item_by_id(pool, *id).await {
+        Ok(i) => i,
+        Err(_) => return HttpResponse::NotFound().body("Item not found"),
+    };
+    match i.set_playback_position(pool, form.position.max(0.0)).await {
+        Ok(_) => HttpResponse::Ok().body("Ok"),
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
 }
 
 #[post("/feeds/{id}/update")]
-pub async fn update_feed(id: web::Path<i64>, state: web::Data<State<'_>>) -> impl Responder {
+pub async fn update_feed(_: SignedIn, id: web::Path<i64>, state: web::Data<State<'_>>) -> impl Responder {
     let pool = &state.pool;
     let hb = &state.hb;
     let mut f = Feed::get_feed_by_id(pool, *id).await.unwrap();
-    let _ = f.update_feed(pool).await.unwrap();
+    // A failed update is recorded on the feed and shown on its page.
+    let _ = f.update_feed(pool, &state.client).await;
+    HttpResponse::Ok().body(f.render(pool, hb, "html/feed").await)
+}
+
+#[derive(Deserialize)]
+pub struct MarkFeedRead {
+    /// `crawled_at` of the newest item shown, empty when there was none.
+    before: Option<String>,
+}
+
+#[post("/feeds/{id}/read")]
+pub async fn mark_feed_read(
+    _: SignedIn,
+    id: web::Path<i64>,
+    form: web::Form<MarkFeedRead>,
+    state: web::Data<State<'_>>,
+) -> impl Responder {
+    let pool = &state.pool;
+    let hb = &state.hb;
+    let f = match Feed::get_feed_by_id(pool, *id).await {
+        Ok(f) => f,
+        Err(err) if err.is_not_found() => return HttpResponse::NotFound().body("Feed not found"),
+        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+    };
+    // Only items already on the page that was rendered are marked read.
+    let before = match form.before.as_deref().filter(|b| !b.is_empty()) {
+        Some(before) => match DateTime::parse_from_rfc3339(before) {
+            Ok(before) => before.with_timezone(&Utc),
+            Err(_) => return HttpResponse::BadRequest().body("Invalid time"),
+        },
+        None => Utc::now(),
+    };
+    if let Err(err) = f.mark_read(pool, before).await {
+        return HttpResponse::InternalServerError().body(err.to_string());
+    }
     HttpResponse::Ok().body(f.render(pool, hb, "html/feed").await)
 }
 
+#[derive(Deserialize)]
+pub struct FullText {
+    /// Present when the checkbox is ticked.
+    full_text: Option<String>,
+}
+
+#[post("/feeds/{id}/full-text")]
+pub async fn set_full_text(
+    _: SignedIn,
+    id: web::Path<i64>,
+    form: web::Form<FullText>,
+    state: web::Data<State<'_>>,
+) -> impl Responder {
+    let pool = &state.pool;
+    let hb = &state.hb;
+    let mut f = match Feed::get_feed_by_id(pool, *id).await {
+        Ok(f) => f,
+        Err(err) if err.is_not_found() => return HttpResponse::NotFound().body("Feed not found"),
+        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+    };
+    match f.set_full_text(pool, form.full_text.is_some()).await {
+        Ok(_) => HttpResponse::Ok().body(f.render(pool, hb, "html/feed").await),
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct FetchInterval {
+    /// Minutes between fetches; empty to use the default.
+    interval: String,
+}
+
+#[post("/feeds/{id}/interval")]
+pub async fn set_fetch_interval(
+    _: SignedIn,
+    id: web::Path<i64>,
+    form: web::Form<FetchInterval>,
+    state: web::Data<State<'_>>,
+) -> impl Responder {
+    let pool = &state.pool;
+    let hb = &state.hb;
+    let interval = match form.interval.trim() {
+        "" => None,
+        minutes => match minutes.parse::<i64>() {
+            Ok(minutes) if minutes > 0 => Some(minutes * 60),
+            _ => return HttpResponse::BadRequest().body("Interval must be a positive number of minutes"),
+        },
+    };
+    let mut f = match Feed::get_feed_by_id(pool, *id).await {
+        Ok(f) => f,
+        Err(err) if err.is_not_found() => return HttpResponse::NotFound().body("Feed not found"),
+        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+    };
+    match f.set_fetch_interval(pool, interval).await {
+        Ok(_) => HttpResponse::Ok().body(f.render(pool, hb, "html/feed").await),
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
+}
+
 #[post("/feeds/{id}/delete")]
-pub async fn delete_feed(id: web::Path<i64>, state: web::Data<State<'_>>) -> impl Responder {
+pub async fn delete_feed(_: SignedIn, id: web::Path<i64>, state: web::Data<State<'_>>) -> impl Responder {
     let pool = &state.pool;
     let f = Feed::get_feed_by_id(pool, *id).await.unwrap();
     f.delete(pool).await.unwrap();
     HttpResponse::Ok().body(format!("Deleted {}", f.title))
 }
+
+#[get("/opml")]
+pub async fn export_opml(_: SignedIn, state: web::Data<State<'_>>) -> impl Responder {
+    match opml::export(&state.pool).await {
+        Ok(opml) => HttpResponse::Ok()
+            .content_type("text/xml")
+            .insert_header(("Content-Disposition", "attachment; filename=\"liu.opml\""))
+            .body(opml),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+#[post("/opml")]
+pub async fn import_opml(_: SignedIn, mut payload: Multipart, state: web::Data<State<'_>>) -> impl Responder {
+    let hb = &state.hb;
+    let mut src = Vec::new();
+    while let Some(field) = payload.next().await {
+        let mut field = match field {
+            Ok(field) => field,
+            Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+        };
+        // Only the file input holds the document.
+        if field.name() != "opml" {
+            continue;
+        }
+        while let Some(chunk) = field.next().await {
+            match chunk {
+                Ok(chunk) => src.extend_from_slice(&chunk),
+                Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+            }
+            if src.len() > MAX_OPML_SIZE {
+                return HttpResponse::PayloadTooLarge().body("OPML file too large");
+            }
+        }
+    }
+    let results = match opml::import(&state.pool, &state.client, &String::from_utf8_lossy(&src)).await {
+        Ok(results) => results,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    let feeds = results
+        .iter()
+        .map(|r| match &r.status {
+            ImportStatus::Imported(feed) => json!({"id": feed.id, "title": feed.title, "url": r.uri, "status": "Imported"}),
+            ImportStatus::Exists => json!({"title": r.title, "url": r.uri, "status": "Already subscribed"}),
+            ImportStatus::Failed(e) => json!({"title": r.title, "url": r.uri, "status": format!("Failed: {}", e)}),
+        })
+        .collect::<Vec<_>>();
+    HttpResponse::Ok().body(hb.render("html/import", &json!({ "feeds": feeds })).unwrap())
+}
+
+#[get("/icons/{feed_id}")]
+pub async fn get_icon(feed_id: web::Path<i64>, state: web::Data<State<'_>>) -> impl Responder {
+    match Icon::get_icon(&state.pool, *feed_id).await {
+        Ok(Some(icon)) => HttpResponse::Ok()
+            .content_type(icon.mime_type)
+            .insert_header(("Cache-Control", "public, max-age=86400"))
+            .insert_header(("X-Content-Type-Options", "nosniff"))
+            .insert_header(("Content-Security-Policy", "default-src 'none'; style-src 'unsafe-inline'; sandbox"))
+            .body(icon.data),
+        Ok(None) => HttpResponse::NotFound().body("Feed has no icon"),
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
+}
+
+#[get("/proxy/{token}")]
+pub async fn proxied(token: web::Path<String>, state: web::Data<State<'_>>) -> impl Responder {
+    let url = match state.proxy.verify(&token) {
+        Some(url) => url,
+        None => return HttpResponse::Forbidden().body("Invalid signature"),
+    };
+    match state.proxy.fetch(&url).await {
+        Ok(Some((content_type, body))) => HttpResponse::Ok()
+            .content_type(content_type)
+            .insert_header(("Cache-Control", "public, max-age=31536000, immutable"))
+            .insert_header(("X-Content-Type-Options", "nosniff"))
+            // SVG images may carry scripts; never let them run on this origin.
+            .insert_header(("Content-Security-Policy", "default-src 'none'; style-src 'unsafe-inline'; sandbox"))
+            .body(body),
+        Ok(None) => HttpResponse::UnsupportedMediaType().body("Not a public image, or too large"),
+        Err(err) => HttpResponse::BadGateway().body(err.to_string()),
+    }
+}
+
+/// Response headers of a streamed video passed on to the reader.
+const STREAMED_HEADERS: [http::header::HeaderName; 5] = [
+    http::header::CONTENT_TYPE,
+    http::header::CONTENT_RANGE,
+    http::header::ACCEPT_RANGES,
+    http::header::ETAG,
+    http::header::LAST_MODIFIED,
+];
+
+#[get("/proxy/media/{token}")]
+pub async fn proxied_media(req: HttpRequest, token: web::Path<String>, state: web::Data<State<'_>>) -> impl Responder {
+    let url = match state.proxy.verify(&token) {
+        Some(url) => url,
+        None => return HttpResponse::Forbidden().body("Invalid signature"),
+    };
+    let header = |name| req.headers().get(name).map(|value| value.as_bytes());
+    let response = match state.proxy.stream(&url, header(http::header::RANGE), header(http::header::IF_RANGE)).await {
+        Ok(Some(response)) => response,
+        Ok(None) => return HttpResponse::UnsupportedMediaType().body("Not a public video"),
+        Err(err) => return HttpResponse::BadGateway().body(err.to_string()),
+    };
+    let mut builder = HttpResponse::build(response.status());
+    for name in STREAMED_HEADERS {
+        if let Some(value) = response.headers().get(&name) {
+            builder.insert_header((name, value.clone()));
+        }
+    }
+    builder
+        // Compressing would break the byte ranges the reader asked for.
+        .insert_header(http::header::ContentEncoding::Identity)
+        .insert_header(("X-Content-Type-Options", "nosniff"))
+        .insert_header(("Content-Security-Policy", "default-src 'none'; sandbox"));
+    let length = response.content_length();
+    let stream = response.bytes_stream().map(|chunk| chunk.map_err(error::ErrorBadGateway));
+    match length {
+        Some(length) => builder.body(body::SizedStream::new(length, stream)),
+        None => builder.streaming(stream),
+    }
+}